@@ -0,0 +1,40 @@
+//! Decode arbitrary frame bodies as every relay message type.
+//!
+//! The decoder is included by path, as weesels only builds a binary.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+#[allow(dead_code)]
+#[path = "../../src/wee/de.rs"]
+mod de;
+#[allow(dead_code)]
+#[path = "../../src/wee/messages.rs"]
+mod messages;
+
+use messages::*;
+use serde::Deserialize;
+
+/// Only a date, so that inputs easily reach the tim decoding.
+#[allow(dead_code)]
+#[derive(Deserialize)]
+struct Dated {
+    #[serde(deserialize_with = "messages::timestamp")]
+    date: time::OffsetDateTime,
+}
+
+fuzz_target!(|data: &[u8]| {
+    let _ = de::from_bytes::<HandshakeResponse>(data);
+    let _ = de::from_bytes::<Info>(data);
+    let _ = de::from_bytes::<BuffersResponse>(data);
+    let _ = de::from_bytes::<Hdata<Hotlist>>(data);
+    let _ = de::from_bytes::<Hdata<LineAddr>>(data);
+    let _ = de::from_bytes::<Hdata<LineData>>(data);
+    let _ = de::from_bytes::<LineAddedEvent>(data);
+    let _ = de::from_bytes::<Hdata<NicklistItem>>(data);
+    let _ = de::from_bytes::<CompletionResponse>(data);
+    let _ = de::from_bytes::<Hdata<BufferEvent>>(data);
+    let _ = de::from_bytes::<Pong>(data);
+    let _ = de::from_bytes::<Hdata<Dated>>(data);
+    let _ = de::from_bytes::<(String, Dated)>(data);
+    let _ = de::peek_str(data);
+});
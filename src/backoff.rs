@@ -0,0 +1,44 @@
+use std::time::Duration;
+
+/// Exponential backoff for retrying connections.
+pub struct Backoff {
+    min: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl Backoff {
+    pub fn new(min: Duration, max: Duration) -> Self {
+        Self {
+            min,
+            max,
+            current: min,
+        }
+    }
+
+    /// Return the delay before the next attempt, doubling the following one.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = Duration::min(self.max, self.current * 2);
+        delay
+    }
+
+    /// Restart from the minimal delay, after a successful attempt.
+    pub fn reset(&mut self) {
+        self.current = self.min;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let mut b = Backoff::new(Duration::from_secs(1), Duration::from_secs(5));
+        let delays: Vec<u64> = (0..5).map(|_| b.next_delay().as_secs()).collect();
+        assert_eq!(vec![1, 2, 4, 5, 5], delays);
+        b.reset();
+        assert_eq!(Duration::from_secs(1), b.next_delay());
+    }
+}
@@ -16,6 +16,14 @@ pub struct CmdConf {
     /// path to config file
     #[argh(option)]
     pub config: Option<PathBuf>,
+
+    /// record the frames exchanged with the relay to a file
+    #[argh(option)]
+    pub record: Option<PathBuf>,
+
+    /// replay a recorded session instead of connecting
+    #[argh(option)]
+    pub replay: Option<PathBuf>,
 }
 
 impl CmdConf {
@@ -0,0 +1,56 @@
+//! Commands handled by the client, instead of being sent to the relay.
+use crate::wee::SyncProfile;
+
+const PREFIX: &str = "/weesels";
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    /// Change the sync profile of a buffer, the current one by default.
+    Sync(SyncProfile, Option<String>),
+}
+
+/// Parse an input line. Returns None for input meant for the relay, and an
+/// usage message for invalid commands.
+pub fn parse(input: &str) -> Option<Result<Command, &'static str>> {
+    let args = input.strip_prefix(PREFIX)?;
+    if !args.is_empty() && !args.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let mut args = args.split_whitespace();
+    Some(match (args.next(), args.next(), args.next(), args.next()) {
+        (Some("sync"), Some(profile), buffer, None) => match profile.parse() {
+            Ok(profile) => Ok(Command::Sync(profile, buffer.map(String::from))),
+            Err(()) => Err(SYNC_USAGE),
+        },
+        (Some("sync"), ..) => Err(SYNC_USAGE),
+        _ => Err("Usage: /weesels sync ..."),
+    })
+}
+
+const SYNC_USAGE: &str = "Usage: /weesels sync full|buffer|none [buffer]";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(None, parse("hello"));
+        assert_eq!(None, parse("/weeselsync"));
+        assert_eq!(
+            Some(Ok(Command::Sync(SyncProfile::Buffer, None))),
+            parse("/weesels sync buffer")
+        );
+        assert_eq!(
+            Some(Ok(Command::Sync(
+                SyncProfile::None,
+                Some(String::from("irc.libera.#rust"))
+            ))),
+            parse("/weesels sync none irc.libera.#rust")
+        );
+        assert_eq!(Some(Err(SYNC_USAGE)), parse("/weesels sync all"));
+        assert_eq!(Some(Err(SYNC_USAGE)), parse("/weesels sync"));
+        assert!(matches!(parse("/weesels quit"), Some(Err(_))));
+        assert!(matches!(parse("/weesels"), Some(Err(_))));
+    }
+}
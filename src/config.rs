@@ -1,10 +1,13 @@
-use serde::Deserialize;
+use regex::Regex;
+use serde::{de, Deserialize, Deserializer};
+use std::collections::HashMap;
 use std::io::Read;
 use std::{fs::File, path::PathBuf};
 
 use crate::cli::CmdConf;
 use crate::errors::Error;
 use crate::errors::ErrorKind::ConfigError;
+use crate::wee::SyncProfile;
 
 type Res<T> = Result<T, Error>;
 
@@ -12,13 +15,75 @@ const CONFIG_FILENAME: &str = "weesels.conf";
 
 #[derive(Deserialize)]
 pub struct Conf {
+    /// Relay host name, a ws:// or wss:// URL for a relay behind an HTTP
+    /// reverse proxy, or "unix:" followed by the path of a local socket.
+    #[serde(default)]
     pub host: String,
-    pub port: u16,
+    /// Relay port, ignored for URLs.
+    pub port: Option<u16>,
     pub password: String,
     #[serde(default = "default_ssl")]
     pub ssl: bool,
+    /// Accept any relay certificate, without verification.
     #[serde(default = "default_insecure")]
     pub insecure: bool,
+    /// PEM file of CA certificates trusted for the relay certificate, in
+    /// addition to the system ones.
+    pub ca_file: Option<PathBuf>,
+    /// SHA-256 fingerprint of the relay certificate, in hex. The certificate
+    /// is trusted when it matches, without CA verification.
+    pub fingerprint: Option<String>,
+    /// PEM files of the client certificate and key, for relays requiring
+    /// one. The key may be in the certificate file.
+    pub client_cert: Option<PathBuf>,
+    pub client_key: Option<PathBuf>,
+    /// Comma-separated compressions offered in the handshake, by order of
+    /// preference (e.g. "zstd,zlib,off").
+    #[serde(default = "default_compression")]
+    pub compression: String,
+    /// Base32 secret matching `relay.network.totp_secret`. The code is
+    /// prompted for when missing.
+    pub totp_secret: Option<String>,
+    /// Proxy to reach the relay through, as a "socks5://" or "http://" URL
+    /// with optional "user:password@" credentials.
+    pub proxy: Option<String>,
+    /// Path of the local UNIX socket of the relay, replacing the host.
+    pub socket_path: Option<PathBuf>,
+    /// Extra HTTP headers of the WebSocket handshake.
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// Seconds between pings measuring the lag, 0 to disable them.
+    #[serde(default = "default_ping_interval")]
+    pub ping_interval: u64,
+    /// Seconds to wait for the answer to a ping before reconnecting.
+    #[serde(default = "default_ping_timeout")]
+    pub ping_timeout: u64,
+    /// Sync profiles by buffer full name, which may end with "*". Buffers
+    /// are synced fully by default.
+    #[serde(default)]
+    pub sync: HashMap<String, SyncProfile>,
+    /// Client-side filters hiding lines.
+    #[serde(default, deserialize_with = "filters")]
+    pub filters: Vec<FilterConf>,
+}
+
+/// Filter hiding the lines matching all its conditions, like WeeChat's
+/// /filter.
+#[derive(Deserialize)]
+pub struct FilterConf {
+    /// Comma-separated full names of the filtered buffers, which may end
+    /// with "*".
+    #[serde(default = "default_filter_buffers")]
+    pub buffers: String,
+    /// Tags of hidden lines, any of them. Tags joined with "+" must all be
+    /// on the line, and may end with "*".
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Regexes of the prefix and message of hidden lines, without colors.
+    #[serde(default, deserialize_with = "regex")]
+    pub prefix: Option<Regex>,
+    #[serde(default, deserialize_with = "regex")]
+    pub message: Option<Regex>,
 }
 
 fn default_ssl() -> bool {
@@ -29,6 +94,42 @@ fn default_insecure() -> bool {
     false
 }
 
+fn default_compression() -> String {
+    String::from("zstd,zlib,off")
+}
+
+fn default_ping_interval() -> u64 {
+    30
+}
+
+fn default_ping_timeout() -> u64 {
+    60
+}
+
+fn default_filter_buffers() -> String {
+    String::from("*")
+}
+
+/// Filters, each with a condition: others would hide whole buffers.
+fn filters<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<FilterConf>, D::Error> {
+    let filters = Vec::<FilterConf>::deserialize(deserializer)?;
+    if filters
+        .iter()
+        .any(|f| f.tags.is_empty() && f.prefix.is_none() && f.message.is_none())
+    {
+        return Err(de::Error::custom(
+            "filters need tags, a prefix or a message to match",
+        ));
+    }
+    Ok(filters)
+}
+
+fn regex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Regex>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|r| Regex::new(&r).map_err(de::Error::custom))
+        .transpose()
+}
+
 pub struct Loader {
     prefix: PathBuf,
 }
@@ -74,14 +175,36 @@ mod tests {
     #[test]
     fn test_load() {
         let mut f = NamedTempFile::new().unwrap();
-        f.write(b"host='some.place'\nport=1235\npassword='flubar'\n")
+        f.write(b"host='some.place'\nport=1235\npassword='flubar'\n[sync]\n'irc.*'='buffer'\n")
             .unwrap();
 
         let c = super::load(f.into_temp_path().as_ref());
         let c = c.expect("should read config");
         assert_eq!("some.place", c.host);
-        assert_eq!(1235, c.port);
+        assert_eq!(Some(1235), c.port);
         assert_eq!("flubar", c.password);
+        assert_eq!("zstd,zlib,off", c.compression);
+        assert_eq!(30, c.ping_interval);
+        assert_eq!(Some(&SyncProfile::Buffer), c.sync.get("irc.*"));
+    }
+
+    #[test]
+    fn test_load_filters() {
+        let load = |filters: &str| {
+            let mut f = NamedTempFile::new().unwrap();
+            f.write_all(format!("host='some.place'\npassword='flubar'\n{}", filters).as_bytes())
+                .unwrap();
+            super::load(f.into_temp_path().as_ref())
+        };
+        let c = load("[[filters]]\ntags = ['irc_join']\nmessage = '^bot'\n").unwrap();
+        assert_eq!("*", c.filters[0].buffers);
+        assert!(c.filters[0]
+            .message
+            .as_ref()
+            .unwrap()
+            .is_match("bot joined"));
+        assert!(load("[[filters]]\nmessage = '('\n").is_err());
+        assert!(load("[[filters]]\nbuffers = 'irc.*'\n").is_err());
     }
 
     #[test]
@@ -1,4 +1,5 @@
-#![recursion_limit = "1024"]
+#![recursion_limit = "2048"]
+use futures::future::{Fuse, LocalBoxFuture};
 use futures::select;
 use futures::FutureExt;
 use log::{error, info, trace};
@@ -8,15 +9,26 @@ use smol::io::AsyncReadExt;
 use smol::net::unix::UnixStream;
 use std::error::Error;
 use std::process::exit;
+use std::time::{Duration, Instant};
 use termion::raw::IntoRawMode;
 use ui::input::Action;
 
+mod backoff;
 mod cli;
+mod command;
 mod config;
 mod errors;
 mod ui;
 mod wee;
 
+/// Lag from which it is shown in the status line.
+const LAG_MIN_SHOW: Duration = Duration::from_millis(500);
+/// Interval at which the status line is refreshed while idle, to show the
+/// lag growing.
+const STATUS_REFRESH: Duration = Duration::from_secs(1);
+/// Time given to reconnect and resync, before trying again.
+const RECONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
 /// Initializes logging. Terminates process with code 1 on error.
 fn init_logging(conf: &cli::CmdConf) {
     let default_log =
@@ -46,8 +58,6 @@ fn init_logging(conf: &cli::CmdConf) {
 }
 
 fn main() {
-    let _p = setup_panic();
-
     let conf = cli::CmdConf::from_env();
     init_logging(&conf);
 
@@ -65,18 +75,26 @@ async fn get_input(stdin: &mut smol::fs::File) -> Result<String, Box<dyn Error>>
     Ok(String::from_utf8(input)?)
 }
 
-async fn run(conf: cli::CmdConf) -> Result<(), Box<dyn Error>> {
-    let conf = config::Loader::new()?.load(&conf)?;
-
-    let mut wee = wee::Wee::connect(&conf).await?;
-    wee.buffers().await?;
-    wee.run().await?; // actually send request
-    wee.run().await?; // receive initial buf list
-    wee.switch_current_buffer(&String::from("core.weechat"))
-        .await?; // request buffer data
-    wee.send("sync", "sync").await?; // subscribe to events
+async fn run(cmd: cli::CmdConf) -> Result<(), Box<dyn Error>> {
+    // replays need no relay, nor its configuration.
+    let (mut wee, conf) = match &cmd.replay {
+        Some(path) => (wee::Wee::replay(path)?, None),
+        None => {
+            let conf = config::Loader::new()?.load(&cmd)?;
+            (wee::Wee::connect(&conf, prompt_totp).await?, Some(conf))
+        }
+    };
+    if let Some(path) = &cmd.record {
+        wee.record(path)?;
+    }
+    wee.sync().await?;
 
+    // the TOTP code is prompted for before switching the terminal to raw mode.
+    let _p = setup_panic();
     let mut ui = ui::Ui::new();
+    if let Some(conf) = &conf {
+        ui.filters = ui::filter::Filters::new(&conf.filters);
+    }
     ui.draw(&wee);
     let mut stdin = smol::fs::File::from(termion::get_tty()?);
 
@@ -87,25 +105,110 @@ async fn run(conf: cli::CmdConf) -> Result<(), Box<dyn Error>> {
     // XXX track multiple?
     let mut notification: Option<NotificationHandle> = None;
 
+    let mut backoff = backoff::Backoff::new(Duration::from_secs(1), Duration::from_secs(300));
+    // Time of the next reconnection attempt, while disconnected.
+    let mut retry_at: Option<Instant> = None;
+    // Why the last reconnection attempt failed.
+    let mut retry_error: Option<wee::Error> = None;
+    // Status line last set by `status()`, while connected.
+    let mut shown_status: Option<String> = None;
+
+    // Pending completion request, replaced by any newer completion.
+    let mut completion: Fuse<LocalBoxFuture<Result<Option<wee::CompletionData>, wee::Error>>> =
+        Fuse::terminated();
+
     loop {
         select! {
             () = signals.wait().fuse() => {
                 ui.draw(&wee);
             },
-            incoming = wee.run().fuse() => {
-                incoming?;
-                if wee.get_current_buffer().is_none() {
-                    wee.switch_current_buffer(&String::from("core.weechat")).await?;
+            completed = completion => {
+                match completed {
+                    Ok(Some(data)) => {
+                        ui.input.complete(data);
+                        ui.draw(&wee);
+                    }
+                    Ok(None) => (),
+                    Err(e) => error!("Completion failed: {}", e),
                 }
+            },
+            () = retry_tick(retry_at).fuse() => {
+                let at = retry_at.expect("ticking while connected");
+                let now = Instant::now();
+                if now >= at {
+                    ui.status = Some(String::from("Disconnected, reconnecting..."));
+                    ui.draw(&wee);
+                    let conf = conf.as_ref().expect("reconnecting a replay");
+                    match reconnect(&mut wee, conf).await {
+                        Ok(()) => {
+                            info!("Reconnected");
+                            backoff.reset();
+                            retry_at = None;
+                            retry_error = None;
+                            ui.status = None;
+                            shown_status = None;
+                        }
+                        Err(e) => {
+                            error!("Reconnection failed: {}", e);
+                            retry_at = Some(now + backoff.next_delay());
+                            retry_error = Some(e);
+                        }
+                    }
+                }
+                if let Some(at) = retry_at {
+                    let secs = at.saturating_duration_since(now).as_secs();
+                    ui.status = Some(match &retry_error {
+                        Some(e) => format!("Reconnection failed: {}, retrying in {}s", e, secs),
+                        None => format!("Disconnected, retrying in {}s", secs),
+                    });
+                }
+                ui.draw(&wee);
+            },
+            incoming = async {
+                match retry_at {
+                    None => wee.run().await,
+                    Some(_) => futures::future::pending().await,
+                }
+            }.fuse() => {
+                let res = match incoming {
+                    Ok(()) if wee.get_current_buffer().is_none() => {
+                        wee.switch_current_buffer(&String::from("core.weechat")).await
+                    }
+                    res => res,
+                };
+                if let Err(e) = res {
+                    schedule_reconnect(e, conf.is_some(), &mut backoff, &mut retry_at, &mut ui)?;
+                    ui.draw(&wee);
+                    continue;
+                }
+                show_status(&mut ui, &mut shown_status, &wee);
                 notification = desktop_notify(notification, &wee);
                 ui.draw(&wee);
+            },
+            () = status_tick(retry_at).fuse() => {
+                if show_status(&mut ui, &mut shown_status, &wee) {
+                    ui.draw(&wee);
+                }
             }
             input = get_input(&mut stdin).fuse() => {
                 match input {
                     Ok(s) => {
                         match ui.input.handle_input(s) {
+                            Action::Input if retry_at.is_some() || wee.is_upgrading() => {
+                                // keep the input until reconnected or upgraded
+                                ui.draw(&wee);
+                            }
                             Action::Input => {
-                                if let Some(buf) = wee.get_current_buffer() {
+                                if let Some(command) = command::parse(&ui.input.get_string()) {
+                                    match run_command(&mut wee, command).await {
+                                        Ok(message) => {
+                                            ui.status = Some(message);
+                                            ui.input.clear();
+                                        }
+                                        Err(e) => schedule_reconnect(e, conf.is_some(), &mut backoff, &mut retry_at, &mut ui)?,
+                                    }
+                                    ui.draw(&wee);
+                                } else if let Some(buf) = wee.get_current_buffer() {
                                     // send lines separately, as protocol does not handle newlines
                                     for line in ui.input.get_string().lines() {
                                         wee.send("", format!("input {} {}", buf.full_name, line).as_str()).await?;
@@ -129,9 +232,11 @@ async fn run(conf: cli::CmdConf) -> Result<(), Box<dyn Error>> {
                                                     prev
                                                 };
                                                 if let Some(new_buf) = new_buf {
-
-                                                    trace!("buf change {}", new_buf.full_name);
-                                                    wee.switch_current_buffer(&new_buf.full_name).await?;
+                                                    let name = new_buf.full_name.clone();
+                                                    trace!("buf change {}", name);
+                                                    if let Err(e) = wee.switch_current_buffer(&name).await {
+                                                        schedule_reconnect(e, conf.is_some(), &mut backoff, &mut retry_at, &mut ui)?;
+                                                    }
                                                     ui.draw(&wee);
                                                 }
                                                 break;
@@ -143,18 +248,32 @@ async fn run(conf: cli::CmdConf) -> Result<(), Box<dyn Error>> {
                                 };
                             },
                             Action::BufChangeAbs(i) => {
-                                if let Some(b) = &wee.get_buffers().get(i){
-                                    wee.switch_current_buffer(&b.full_name).await?
+                                if let Some(name) = wee.get_buffers().get(i).map(|b| b.full_name.clone()) {
+                                    if let Err(e) = wee.switch_current_buffer(&name).await {
+                                        schedule_reconnect(e, conf.is_some(), &mut backoff, &mut retry_at, &mut ui)?;
+                                    }
+                                    ui.draw(&wee);
                                 };
                             }
+                            Action::ToggleFilters => {
+                                if let Some(buf) = wee.get_current_buffer() {
+                                    let enabled = ui.filters.toggle(&buf.full_name);
+                                    ui.status = Some(format!(
+                                        "Filters {} in {}",
+                                        if enabled { "enabled" } else { "disabled" },
+                                        buf.full_name
+                                    ));
+                                    ui.draw(&wee);
+                                }
+                            }
                             Action::ScrollBack => {
-                                wee.scroll_back(10).await?;
-
+                                if let Err(e) = wee.scroll_back(10).await {
+                                    schedule_reconnect(e, conf.is_some(), &mut backoff, &mut retry_at, &mut ui)?;
+                                    ui.draw(&wee);
+                                }
                             }
                             Action::Completion(pos, data) => {
-                                if let Some(buf) = wee.get_current_buffer() {
-                                    wee.send("completion", &format!("completion 0x{} {} {}", buf.ptr_buffer, pos, data)).await?;
-                                }
+                                completion = wee.complete(pos, &data).boxed_local().fuse();
                             },
                             _ => ui.draw(&wee),
                         };
@@ -174,6 +293,115 @@ async fn run(conf: cli::CmdConf) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+/// Status line while connected, for upgrades and noticeable lag.
+fn status(wee: &wee::Wee) -> Option<String> {
+    if wee.is_upgrading() {
+        return Some(String::from("WeeChat is upgrading..."));
+    }
+    match wee.lag() {
+        Some(lag) if lag >= LAG_MIN_SHOW => Some(format!("Lag: {:.1}s", lag.as_secs_f32())),
+        _ => None,
+    }
+}
+
+/// Update the status line from `status()`, returning whether it changed.
+/// Other messages are kept, unless a new status appears.
+fn show_status(ui: &mut ui::Ui, shown: &mut Option<String>, wee: &wee::Wee) -> bool {
+    let new = status(wee);
+    let replace = ui.status == *shown || (shown.is_none() && new.is_some());
+    let changed = replace && ui.status != new;
+    if replace {
+        ui.status = new.clone();
+    }
+    *shown = new;
+    changed
+}
+
+/// Run a client command, and return the message to show.
+async fn run_command(
+    wee: &mut wee::Wee,
+    command: Result<command::Command, &'static str>,
+) -> Result<String, wee::Error> {
+    match command {
+        Err(usage) => Ok(String::from(usage)),
+        Ok(command::Command::Sync(profile, buffer)) => {
+            let name =
+                match buffer.or_else(|| wee.get_current_buffer().map(|b| b.full_name.clone())) {
+                    Some(name) => name,
+                    None => return Ok(String::from("No current buffer")),
+                };
+            wee.set_sync_profile(&name, profile).await?;
+            Ok(format!("Sync profile of {}: {}", name, profile))
+        }
+    }
+}
+
+/// Schedule a reconnection when `error` means the connection is lost, and
+/// reconnecting is possible. Other errors are returned.
+fn schedule_reconnect(
+    error: wee::Error,
+    can_reconnect: bool,
+    backoff: &mut backoff::Backoff,
+    retry_at: &mut Option<Instant>,
+    ui: &mut ui::Ui,
+) -> Result<(), wee::Error> {
+    if !error.is_disconnect() || !can_reconnect {
+        return Err(error);
+    }
+    error!("Connection lost: {}", error);
+    let delay = backoff.next_delay();
+    *retry_at = Some(Instant::now() + delay);
+    ui.status = Some(format!("Disconnected, retrying in {}s", delay.as_secs()));
+    Ok(())
+}
+
+/// Reconnect and resync the relay state, giving up after
+/// `RECONNECT_TIMEOUT`.
+async fn reconnect(wee: &mut wee::Wee, conf: &config::Conf) -> Result<(), wee::Error> {
+    let reconnect = async {
+        wee.reconnect(conf).await?;
+        wee.sync().await
+    };
+    let timeout = async {
+        smol::Timer::after(RECONNECT_TIMEOUT).await;
+        Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "reconnection timed out").into())
+    };
+    smol::future::or(reconnect, timeout).await
+}
+
+/// Wake up every second until `retry_at`, or never when connected.
+async fn retry_tick(retry_at: Option<Instant>) {
+    match retry_at {
+        Some(at) => {
+            smol::Timer::at(Instant::min(at, Instant::now() + Duration::from_secs(1))).await;
+        }
+        None => futures::future::pending().await,
+    }
+}
+
+/// Wake up after `STATUS_REFRESH`, or never when disconnected.
+async fn status_tick(retry_at: Option<Instant>) {
+    match retry_at {
+        Some(_) => futures::future::pending().await,
+        None => {
+            smol::Timer::after(STATUS_REFRESH).await;
+        }
+    }
+}
+
+/// Ask for a TOTP code on the terminal, before the TUI starts.
+fn prompt_totp() -> std::io::Result<String> {
+    use std::io::Write;
+    use termion::input::TermRead;
+
+    let mut tty = termion::get_tty()?;
+    tty.write_all(b"TOTP code: ")?;
+    tty.flush()?;
+    let code = tty.read_line()?;
+    tty.write_all(b"\r\n")?;
+    code.ok_or_else(|| std::io::Error::new(std::io::ErrorKind::Interrupted, "TOTP prompt aborted"))
+}
+
 struct Signals {
     inner: UnixStream,
 }
@@ -0,0 +1,412 @@
+use std::iter::Peekable;
+use std::str::Chars;
+use tui::style::{Color, Modifier, Style};
+use tui::text::Span;
+
+/// Text decoded from a weechat string with color codes, as styled segments.
+///
+/// See https://weechat.org/files/doc/devel/weechat_dev.en.html#color_codes_in_strings
+#[derive(Debug, Default, PartialEq)]
+pub struct StyledText {
+    segments: Vec<(String, Style)>,
+}
+
+/// Styling state while decoding.
+#[derive(Clone, Copy)]
+struct State {
+    fg: Option<Color>,
+    bg: Option<Color>,
+    attrs: Modifier,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            fg: None,
+            bg: None,
+            attrs: Modifier::empty(),
+        }
+    }
+}
+
+impl State {
+    fn style(&self) -> Style {
+        let mut style = Style::default().add_modifier(self.attrs);
+        style.fg = self.fg;
+        style.bg = self.bg;
+        style
+    }
+}
+
+impl StyledText {
+    pub fn parse(input: &str) -> Self {
+        let mut text = StyledText::default();
+        let mut state = State::default();
+        let mut current = String::new();
+        let mut it = input.chars().peekable();
+        while let Some(c) = it.next() {
+            if let '\x19' | '\x1a' | '\x1b' | '\x1c' = c {
+                if !current.is_empty() {
+                    text.segments.push((current, state.style()));
+                    current = String::new();
+                }
+            }
+            match c {
+                '\x19' => color_code(&mut it, &mut state),
+                '\x1a' => {
+                    if let Some(a) = it.next().and_then(attribute) {
+                        state.attrs.insert(a);
+                    }
+                }
+                '\x1b' => {
+                    if let Some(a) = it.next().and_then(attribute) {
+                        state.attrs.remove(a);
+                    }
+                }
+                '\x1c' => state = State::default(),
+                c => current.push(c),
+            }
+        }
+        if !current.is_empty() {
+            text.segments.push((current, state.style()));
+        }
+        text
+    }
+
+    /// Return the text without styling.
+    pub fn plain(&self) -> String {
+        self.segments.iter().map(|(s, _)| s.as_str()).collect()
+    }
+
+    pub fn spans(&self) -> Vec<Span<'static>> {
+        self.segments
+            .iter()
+            .map(|(s, style)| Span::styled(s.clone(), *style))
+            .collect()
+    }
+
+    /// Word-wrap the text to `width` columns, keeping styles.
+    pub fn wrap(&self, width: usize) -> Vec<Vec<Span<'static>>> {
+        let styled: Vec<(char, Style)> = self
+            .segments
+            .iter()
+            .flat_map(|(s, style)| s.chars().map(move |c| (c, *style)))
+            .collect();
+        let plain = self.plain();
+        let mut pos = 0;
+        // Wrapping only drops whitespace, so styles are found back by
+        // matching the wrapped characters in order.
+        textwrap::wrap(plain.as_str(), usize::max(1, width))
+            .iter()
+            .map(|line| {
+                let mut spans: Vec<Span<'static>> = vec![];
+                let mut current = String::new();
+                let mut current_style = None;
+                for c in line.chars() {
+                    while pos < styled.len() && styled[pos].0 != c {
+                        pos += 1;
+                    }
+                    let style = styled.get(pos).map_or_else(Style::default, |s| s.1);
+                    pos += 1;
+                    match current_style {
+                        Some(prev) if prev != style => {
+                            spans.push(Span::styled(current, prev));
+                            current = String::new();
+                        }
+                        _ => (),
+                    }
+                    current_style = Some(style);
+                    current.push(c);
+                }
+                if let Some(style) = current_style {
+                    spans.push(Span::styled(current, style));
+                }
+                spans
+            })
+            .collect()
+    }
+}
+
+/// Decode what follows a color code char (0x19).
+fn color_code(it: &mut Peekable<Chars>, state: &mut State) {
+    match it.peek() {
+        Some('F') => {
+            it.next();
+            attributes(it, state);
+            state.fg = read_color(it);
+        }
+        Some('B') => {
+            it.next();
+            state.bg = read_color(it);
+        }
+        Some('*') => {
+            it.next();
+            attributes(it, state);
+            state.fg = read_color(it);
+            if let Some(',') | Some('~') = it.peek() {
+                it.next();
+                state.bg = read_color(it);
+            }
+        }
+        Some('@') => {
+            state.fg = read_color(it);
+            if let Some(',') | Some('~') = it.peek() {
+                it.next();
+                state.bg = read_color(it);
+            }
+        }
+        // bar specific codes
+        Some('b') => {
+            it.next();
+            it.next();
+        }
+        // emphasis
+        Some('E') => {
+            it.next();
+        }
+        Some('\x1c') => {
+            it.next();
+            state.fg = None;
+            state.bg = None;
+        }
+        Some(c) if c.is_ascii_digit() => {
+            let option: Option<u8> = read_digits(it, 2);
+            state.fg = option.and_then(option_color);
+        }
+        _ => (),
+    }
+}
+
+/// Decode optional attributes preceding a color. Attributes are replaced,
+/// unless the keep attributes marker is present.
+fn attributes(it: &mut Peekable<Chars>, state: &mut State) {
+    let mut attrs = Modifier::empty();
+    let mut keep = false;
+    while let Some(c) = it.peek() {
+        match c {
+            '|' => keep = true,
+            '%' | '.' => (), // blink and dim are not rendered
+            c => match attribute(*c) {
+                Some(a) => attrs.insert(a),
+                None => break,
+            },
+        }
+        it.next();
+    }
+    if keep {
+        state.attrs.insert(attrs);
+    } else {
+        state.attrs = attrs;
+    }
+}
+
+fn attribute(c: char) -> Option<Modifier> {
+    match c {
+        '\x01' | '*' => Some(Modifier::BOLD),
+        '\x02' | '!' => Some(Modifier::REVERSED),
+        '\x03' | '/' => Some(Modifier::ITALIC),
+        '\x04' | '_' => Some(Modifier::UNDERLINED),
+        _ => None,
+    }
+}
+
+/// Read a standard color (2 digits) or an extended color ("@" and 5 digits).
+fn read_color(it: &mut Peekable<Chars>) -> Option<Color> {
+    if it.peek() == Some(&'@') {
+        it.next();
+        let ext: Option<u32> = read_digits(it, 5);
+        ext.map(|c| Color::Indexed((c & 0xff) as u8))
+    } else {
+        read_digits(it, 2).and_then(standard_color)
+    }
+}
+
+fn read_digits<T: std::str::FromStr>(it: &mut Peekable<Chars>, count: usize) -> Option<T> {
+    let mut digits = String::new();
+    for _ in 0..count {
+        match it.peek() {
+            Some(c) if c.is_ascii_digit() => digits.push(*c),
+            _ => break,
+        }
+        it.next();
+    }
+    digits.parse().ok()
+}
+
+/// Weechat color names, by standard color index from 1.
+const COLOR_NAMES: [&str; 16] = [
+    "black",
+    "darkgray",
+    "red",
+    "lightred",
+    "green",
+    "lightgreen",
+    "brown",
+    "yellow",
+    "blue",
+    "lightblue",
+    "magenta",
+    "lightmagenta",
+    "cyan",
+    "lightcyan",
+    "gray",
+    "white",
+];
+
+/// Map a weechat color name, or terminal color number, to a color.
+pub fn named_color(name: &str) -> Option<Color> {
+    match COLOR_NAMES.iter().position(|n| *n == name) {
+        Some(index) => standard_color(index as u8 + 1),
+        None => Some(Color::Indexed(name.parse().ok()?)),
+    }
+}
+
+fn standard_color(index: u8) -> Option<Color> {
+    Some(match index {
+        1 => Color::Black,
+        2 => Color::DarkGray,
+        3 => Color::Red,
+        4 => Color::LightRed,
+        5 => Color::Green,
+        6 => Color::LightGreen,
+        7 => Color::Yellow, // brown
+        8 => Color::LightYellow,
+        9 => Color::Blue,
+        10 => Color::LightBlue,
+        11 => Color::Magenta,
+        12 => Color::LightMagenta,
+        13 => Color::Cyan,
+        14 => Color::LightCyan,
+        15 => Color::Gray,
+        16 => Color::White,
+        _ => return None, // default
+    })
+}
+
+/// Default colors of the weechat.color.chat_* options, by option index.
+fn option_color(index: u8) -> Option<Color> {
+    Some(match index {
+        3 | 12 | 33 => Color::Yellow, // time delimiters, server, prefix buffer
+        4 | 29 | 31 => Color::LightYellow, // prefix error, highlight, text found
+        5 | 30 => Color::Magenta,     // prefix network, read marker
+        6 | 11 | 13 | 15 => Color::White, // prefix action, buffer, channel, self
+        7 => Color::LightGreen,       // prefix join
+        8 => Color::LightRed,         // prefix quit
+        9 => Color::LightMagenta,     // prefix more
+        10 | 28 | 40 | 41 => Color::Green, // prefix suffix, delimiters, nick prefix/suffix
+        14 => Color::LightCyan,       // nick
+        16 | 27 | 32 | 43 => Color::Cyan, // nick other, host, value, day change
+        34 => Color::Red,             // tags
+        35..=37 => Color::DarkGray,   // inactive
+        44 => Color::Blue,            // value null
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segments(input: &str) -> Vec<(String, Style)> {
+        StyledText::parse(input).segments
+    }
+
+    #[test]
+    fn test_plain() {
+        let inputs = [
+            "\x1904foobar",
+            "foo\x1901bar",
+            "foo\x19F01bar",
+            "foo\x19B22bar",
+            "foo\x19F@12345bar",
+            "foo\x19@12345,23bar",
+            "foo\x19@12345,@12345bar",
+            "foo\x19@12345~@12345bar",
+            "foo\x19*@12345~@12345bar",
+            "foo\x1a\x01bar\x1c",
+            "foo\x19bFbar",
+            "\x19\x1cfoo\x19Ebar",
+        ];
+        for input in inputs.iter() {
+            assert_eq!(
+                String::from("foobar"),
+                StyledText::parse(input).plain(),
+                "Stripping colors from {:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_colors() {
+        assert_eq!(
+            vec![
+                (String::from("a"), Style::default().fg(Color::Red)),
+                (
+                    String::from("b"),
+                    Style::default().fg(Color::Red).bg(Color::Blue)
+                ),
+                (
+                    String::from("c"),
+                    Style::default()
+                        .fg(Color::Indexed(214))
+                        .bg(Color::Indexed(3))
+                ),
+                (String::from("d"), Style::default()),
+            ],
+            segments("\x19F03a\x19B09b\x19*@00214,@00003c\x1cd")
+        );
+        assert_eq!(
+            vec![(String::from("join"), Style::default().fg(Color::LightGreen))],
+            segments("\x1907join")
+        );
+    }
+
+    #[test]
+    fn test_named_color() {
+        assert_eq!(Some(Color::Black), named_color("black"));
+        assert_eq!(Some(Color::Yellow), named_color("brown"));
+        assert_eq!(Some(Color::White), named_color("white"));
+        assert_eq!(Some(Color::Indexed(214)), named_color("214"));
+        assert_eq!(None, named_color("bar_fg"));
+    }
+
+    #[test]
+    fn test_attributes() {
+        let bold = Style::default().add_modifier(Modifier::BOLD);
+        assert_eq!(
+            vec![
+                (String::from("a"), bold),
+                (
+                    String::from("b"),
+                    Style::default()
+                        .add_modifier(Modifier::BOLD | Modifier::UNDERLINED)
+                        .fg(Color::Green)
+                ),
+                (
+                    String::from("c"),
+                    Style::default()
+                        .add_modifier(Modifier::ITALIC)
+                        .fg(Color::Green)
+                ),
+                (String::from("d"), Style::default().fg(Color::Green)),
+            ],
+            segments("\x1a\x01a\x19F|_05b\x19F/05c\x1b\x03d")
+        );
+        let reversed = Style::default().add_modifier(Modifier::REVERSED);
+        assert_eq!(vec![(String::from("r"), reversed)], segments("\x1a!r"));
+    }
+
+    #[test]
+    fn test_wrap() {
+        let red = Style::default().fg(Color::Red);
+        let wrapped = StyledText::parse("foo \x19F03bar baz").wrap(7);
+        assert_eq!(
+            vec![
+                vec![Span::from("foo "), Span::styled("bar", red)],
+                vec![Span::styled("baz", red)],
+            ],
+            wrapped
+        );
+    }
+}
@@ -0,0 +1,144 @@
+//! Client-side line filters, hiding lines by tags, prefix or message like
+//! WeeChat's /filter.
+use super::color::StyledText;
+use crate::config::FilterConf;
+use crate::wee::LineData;
+use regex::Regex;
+use std::collections::HashSet;
+
+struct Filter {
+    buffers: Vec<String>,
+    /// Lines are hidden when any of these tag sets is on the line.
+    tags: Vec<Vec<String>>,
+    prefix: Option<Regex>,
+    message: Option<Regex>,
+}
+
+impl Filter {
+    fn new(conf: &FilterConf) -> Self {
+        Filter {
+            buffers: conf.buffers.split(',').map(String::from).collect(),
+            tags: conf
+                .tags
+                .iter()
+                .map(|t| t.split('+').map(String::from).collect())
+                .collect(),
+            prefix: conf.prefix.clone(),
+            message: conf.message.clone(),
+        }
+    }
+
+    fn hides(&self, buffer: &str, line: &LineData) -> bool {
+        if !self.buffers.iter().any(|mask| matches(mask, buffer)) {
+            return false;
+        }
+        let has_tag = |mask: &String| line.tags_array.iter().any(|tag| matches(mask, tag));
+        if !self.tags.is_empty() && !self.tags.iter().any(|all| all.iter().all(has_tag)) {
+            return false;
+        }
+        let prefix = line.prefix.as_deref().unwrap_or("");
+        let text_matches = |regex: &Option<Regex>, text: &str| match regex {
+            Some(regex) => regex.is_match(&StyledText::parse(text).plain()),
+            None => true,
+        };
+        text_matches(&self.prefix, prefix) && text_matches(&self.message, &line.message)
+    }
+}
+
+/// Whether `name` matches `mask`, which may end with "*".
+fn matches(mask: &str, name: &str) -> bool {
+    match mask.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => mask == name,
+    }
+}
+
+/// Configured filters, which can be toggled per buffer.
+#[derive(Default)]
+pub struct Filters {
+    filters: Vec<Filter>,
+    /// Full names of buffers showing all their lines.
+    disabled: HashSet<String>,
+}
+
+impl Filters {
+    pub fn new(confs: &[FilterConf]) -> Self {
+        Filters {
+            filters: confs.iter().map(Filter::new).collect(),
+            disabled: HashSet::new(),
+        }
+    }
+
+    /// Whether a line of `buffer` is hidden.
+    pub fn hides(&self, buffer: &str, line: &LineData) -> bool {
+        !self.disabled.contains(buffer) && self.filters.iter().any(|f| f.hides(buffer, line))
+    }
+
+    /// Enable or disable filters of a buffer, returning whether they are now
+    /// enabled.
+    pub fn toggle(&mut self, buffer: &str) -> bool {
+        if self.disabled.remove(buffer) {
+            true
+        } else {
+            self.disabled.insert(String::from(buffer));
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wee::Pointer;
+
+    fn line(prefix: &str, message: &str, tags: &[&str]) -> LineData {
+        LineData {
+            ptr_line: None,
+            ptr_line_data: None,
+            buffer: Pointer(1),
+            date: time::OffsetDateTime::unix_epoch(),
+            displayed: 1,
+            highlight: 0,
+            prefix: Some(String::from(prefix)),
+            message: String::from(message),
+            notify_level: 0,
+            tags_array: tags.iter().map(|t| String::from(*t)).collect(),
+        }
+    }
+
+    fn conf(toml: &str) -> Vec<FilterConf> {
+        #[derive(serde::Deserialize)]
+        struct Conf {
+            filters: Vec<FilterConf>,
+        }
+        toml::from_str::<Conf>(toml).unwrap().filters
+    }
+
+    #[test]
+    fn test_filters() {
+        let mut filters = Filters::new(&conf(
+            "[[filters]]\ntags = ['irc_smart_filter', 'irc_quit+nick_bob']\n\
+             [[filters]]\nbuffers = 'irc.libera.#rust,irc.oftc.*'\nprefix = '^bot$'\n",
+        ));
+        let join = line("-->", "alice joined", &["irc_join", "irc_smart_filter"]);
+        assert!(filters.hides("irc.libera.#rust", &join));
+        let quit = line("<--", "bob quit", &["irc_quit", "nick_bob"]);
+        assert!(filters.hides("irc.libera.#rust", &quit));
+        let quit = line("<--", "carol quit", &["irc_quit", "nick_carol"]);
+        assert!(!filters.hides("irc.libera.#rust", &quit));
+
+        // colors are ignored.
+        let bot = line("\x1904bot", "beep", &["irc_privmsg"]);
+        assert!(filters.hides("irc.libera.#rust", &bot));
+        assert!(filters.hides("irc.oftc.#debian", &bot));
+        assert!(!filters.hides("irc.libera.#weechat", &bot));
+        let talk = line("alice", "hi", &["irc_privmsg"]);
+        assert!(!filters.hides("irc.libera.#rust", &talk));
+
+        assert!(!filters.toggle("irc.libera.#rust"));
+        assert!(!filters.hides("irc.libera.#rust", &join));
+        assert!(filters.hides("irc.oftc.#debian", &join));
+        assert!(filters.toggle("irc.libera.#rust"));
+        assert!(filters.hides("irc.libera.#rust", &join));
+    }
+}
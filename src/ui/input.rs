@@ -12,6 +12,7 @@ pub enum Action {
     BufChange(i8),
     BufChangeAbs(usize),
     ScrollBack,
+    ToggleFilters,
     Noop,
     Quit,
 }
@@ -105,6 +106,7 @@ impl LineEdit {
                             self.data.remove(self.cursor);
                         }
                     }
+                    Key::Alt('-') => return Action::ToggleFilters,
                     Key::Alt(c) => {
                         if let Some(pos) = SHORTCUT_CHARS.find(c) {
                             return Action::BufChangeAbs(pos);
@@ -1,4 +1,6 @@
-use crate::wee::Wee;
+use crate::wee::{group_name, Wee};
+use color::StyledText;
+use filter::Filters;
 use input::LineEdit;
 use std::cell::RefCell;
 use termion::{raw::IntoRawMode, screen::AlternateScreen};
@@ -8,6 +10,8 @@ use tui::style::{Color, Modifier, Style};
 use tui::text::{Span, Spans, Text};
 use tui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph};
 
+mod color;
+pub mod filter;
 pub mod input;
 
 const SHORTCUT_CHARS: &str = "0123456789qwertyuiop";
@@ -33,6 +37,12 @@ const TITLE_DEFAULT_STYLE: Style = Style {
     add_modifier: Modifier::empty(),
     sub_modifier: Modifier::empty(),
 };
+const STATUS_DEFAULT_STYLE: Style = Style {
+    bg: Some(Color::Rgb(60, 60, 60)),
+    fg: Some(Color::Yellow),
+    add_modifier: Modifier::empty(),
+    sub_modifier: Modifier::empty(),
+};
 const INPUT_DEFAULT_STYLE: Style = Style {
     bg: Some(Color::Rgb(30, 30, 30)),
     fg: Some(Color::White),
@@ -45,6 +55,9 @@ type Tui = tui::Terminal<Backend>;
 pub struct Ui {
     tui: RefCell<Tui>,
     pub input: LineEdit,
+    /// Connection status, shown above the input when set.
+    pub status: Option<String>,
+    pub filters: Filters,
 }
 
 impl Ui {
@@ -56,24 +69,30 @@ impl Ui {
         Ui {
             tui: RefCell::new(tui),
             input: LineEdit::new(),
+            status: None,
+            filters: Filters::default(),
         }
     }
 
     pub fn draw(&mut self, wee: &Wee) {
-        if let Some(comp_data) = wee.consume_completion() {
-            self.input.complete(comp_data);
-        }
-        View::new(wee).render(self.tui.get_mut(), &self.input)
+        View::new(wee, self.status.as_deref(), &self.filters)
+            .render(self.tui.get_mut(), &self.input)
     }
 }
 
 struct View<'w> {
     wee: &'w Wee,
+    status: Option<&'w str>,
+    filters: &'w Filters,
 }
 
 impl<'w> View<'w> {
-    pub fn new(wee: &'w Wee) -> Self {
-        Self { wee }
+    pub fn new(wee: &'w Wee, status: Option<&'w str>, filters: &'w Filters) -> Self {
+        Self {
+            wee,
+            status,
+            filters,
+        }
     }
 
     pub fn render(self, tui: &mut Tui, input: &LineEdit) {
@@ -82,14 +101,15 @@ impl<'w> View<'w> {
             let ((cursor_x, cursor_y), input_line) = input.get_wrapped(input_width);
             let current_buffer = self.wee.get_current_buffer();
 
-            let title = if let Some(b) = current_buffer {
-                textwrap::wrap(
-                    match b.title {
-                        Some(ref t) => t.trim(),
-                        None => b.full_name.as_str(),
-                    },
-                    f.size().width as usize,
-                )
+            let title: Vec<Spans> = if let Some(b) = current_buffer {
+                StyledText::parse(match b.title {
+                    Some(ref t) => t.trim(),
+                    None => b.full_name.as_str(),
+                })
+                .wrap(f.size().width as usize)
+                .into_iter()
+                .map(Spans::from)
+                .collect()
             } else {
                 vec![]
             };
@@ -98,7 +118,7 @@ impl<'w> View<'w> {
                 .constraints([Constraint::Length(title.len() as u16), Constraint::Min(1)])
                 .split(f.size());
             f.render_widget(
-                Paragraph::new(Text::from(title.join("\n").as_str())).style(TITLE_DEFAULT_STYLE),
+                Paragraph::new(Text::from(title)).style(TITLE_DEFAULT_STYLE),
                 vlayout[0],
             );
 
@@ -126,7 +146,8 @@ impl<'w> View<'w> {
             }));
             f.render_stateful_widget(buf_list, layout[0], &mut buf_list_state);
             f.render_widget(
-                Paragraph::new("").block(Block::default().title("Nicks").borders(Borders::LEFT)),
+                List::new(self.render_nicklist())
+                    .block(Block::default().title("Nicks").borders(Borders::LEFT)),
                 layout[2],
             );
 
@@ -134,6 +155,7 @@ impl<'w> View<'w> {
                 .direction(Direction::Vertical)
                 .constraints([
                     Constraint::Min(0),
+                    Constraint::Length(if self.status.is_some() { 1 } else { 0 }),
                     Constraint::Length(
                         1 + input_line.chars().filter(|c| c == &'\n').count() as u16,
                     ),
@@ -148,65 +170,71 @@ impl<'w> View<'w> {
                 Paragraph::new(buffer).scroll((buffer_scroll as u16, 0)),
                 center[0],
             );
+            if let Some(status) = self.status {
+                f.render_widget(
+                    Paragraph::new(status).style(STATUS_DEFAULT_STYLE),
+                    center[1],
+                );
+            }
             f.render_widget(
                 Paragraph::new(input_line).style(INPUT_DEFAULT_STYLE),
-                center[1],
+                center[2],
             );
-            f.set_cursor(cursor_x + center[1].x, cursor_y + center[1].y);
+            f.set_cursor(cursor_x + center[2].x, cursor_y + center[2].y);
         })
         .unwrap();
         tui.show_cursor().unwrap();
     }
 
     fn render_buffer(&self, width: Option<u16>) -> Vec<Spans> {
+        let buffer = self
+            .wee
+            .get_current_buffer()
+            .map_or("", |b| b.full_name.as_str());
         let mut list: Vec<Spans> = self
             .wee
             .get_lines()
             .iter()
+            .filter(|line| !self.filters.hides(buffer, line))
             .map(|line| {
-                let secs: i64 = line.date.parse().unwrap();
-                let ts = time::OffsetDateTime::from_unix_timestamp(secs);
+                let ts = line.date;
                 let offset = time::UtcOffset::current_local_offset(); // XXX won't match on DST change, but that's ok.
+                let mut prefix = StyledText::parse(line.prefix.as_deref().unwrap_or("")).spans();
+                if line.highlight != 0 {
+                    for span in prefix.iter_mut() {
+                        span.style = span.style.fg(Color::Yellow);
+                    }
+                }
+                let prefix_width: usize = prefix.iter().map(|s| s.width()).sum();
+                prefix.push(Span::from(" ".repeat(20usize.saturating_sub(prefix_width))));
+                let message = StyledText::parse(line.message.as_str());
                 if let Some(w) = width {
-                    textwrap::wrap(line.message.as_str(), (w - 2u16) as usize)
-                        .iter()
+                    message
+                        .wrap((w - 2u16) as usize)
+                        .into_iter()
                         .enumerate()
                         .map(|(i, m)| {
-                            Spans::from(vec![
-                                if i == 0 {
-                                    Span::from(ts.to_offset(offset).format("%H:%M:%S "))
-                                } else {
-                                    Span::from("          ")
-                                },
-                                if i == 0 {
-                                    let hl = if line.highlight != 0 {
-                                        Style::default().fg(Color::Yellow)
-                                    } else {
-                                        Style::default()
-                                    };
-                                    Span::styled(
-                                        format!(
-                                            "{:<20} ⸽ ",
-                                            line.prefix.as_ref().unwrap_or(&String::from(""))
-                                        ),
-                                        hl,
-                                    )
-                                } else {
-                                    Span::from("                    ⸽ ")
-                                },
-                                Span::from(String::from(m.as_ref())),
-                            ])
+                            let mut spans = if i == 0 {
+                                let mut spans =
+                                    vec![Span::from(ts.to_offset(offset).format("%H:%M:%S "))];
+                                spans.extend(prefix.iter().cloned());
+                                spans.push(Span::from(" ⸽ "));
+                                spans
+                            } else {
+                                vec![
+                                    Span::from("          "),
+                                    Span::from("                    ⸽ "),
+                                ]
+                            };
+                            spans.extend(m);
+                            Spans::from(spans)
                         })
                         .collect()
                 } else {
-                    vec![Spans::from(vec![
-                        Span::from(ts.to_offset(offset).format("%H:%M:%S ")),
-                        Span::from(format!(
-                            "{:<20}",
-                            line.prefix.as_ref().unwrap_or(&String::from("          "))
-                        )),
-                        Span::from(line.message.clone()),
-                    ])]
+                    let mut spans = vec![Span::from(ts.to_offset(offset).format("%H:%M:%S "))];
+                    spans.extend(prefix);
+                    spans.extend(message.spans());
+                    vec![Spans::from(spans)]
                 }
             })
             .flatten()
@@ -217,6 +245,45 @@ impl<'w> View<'w> {
         list
     }
 
+    fn render_nicklist(&self) -> Vec<ListItem<'static>> {
+        let display_groups = match self.wee.get_current_buffer() {
+            Some(b) => b.nicklist_display_groups != 0,
+            None => return vec![],
+        };
+        let nicklist = match self.wee.get_nicklist() {
+            Some(n) => n,
+            None => return vec![],
+        };
+        nicklist
+            .lines(display_groups)
+            .into_iter()
+            .map(|(depth, item)| {
+                let indent = Span::from(" ".repeat(depth));
+                let style = |color: &Option<String>| {
+                    color
+                        .as_deref()
+                        .and_then(color::named_color)
+                        .map_or_else(Style::default, |c| Style::default().fg(c))
+                };
+                if item.group != 0 {
+                    ListItem::new(Spans::from(vec![
+                        indent,
+                        Span::styled(String::from(group_name(&item.name)), style(&item.color)),
+                    ]))
+                } else {
+                    ListItem::new(Spans::from(vec![
+                        indent,
+                        Span::styled(
+                            item.prefix.clone().unwrap_or_default(),
+                            style(&item.prefix_color),
+                        ),
+                        Span::styled(item.name.clone(), style(&item.color)),
+                    ]))
+                }
+            })
+            .collect()
+    }
+
     fn render_buflist(&self) -> Vec<ListItem<'static>> {
         self.wee
             .get_buffers()
@@ -227,7 +294,7 @@ impl<'w> View<'w> {
                     Some(ref s) => s,
                     None => &buf.full_name,
                 };
-                ListItem::new(Spans::from(vec![
+                let mut spans = vec![
                     Span::from(" "),
                     Span::styled(
                         format!(
@@ -236,7 +303,9 @@ impl<'w> View<'w> {
                         ),
                         Style::default().fg(Color::DarkGray),
                     ),
-                    Span::from(name.clone()),
+                ];
+                spans.extend(StyledText::parse(name).spans());
+                spans.extend(vec![
                     Span::from(if buf.hotlist.1 > 0 {
                         format!(" ({})", buf.hotlist.1)
                     } else {
@@ -258,7 +327,8 @@ impl<'w> View<'w> {
                         },
                         Style::default().fg(Color::Red),
                     ),
-                ]))
+                ]);
+                ListItem::new(Spans::from(spans))
             })
             .collect()
     }
@@ -1,76 +1,124 @@
 use super::messages::Handshake;
+use super::Error;
 use openssl::hash::{Hasher, MessageDigest};
 
 /// A colon-separated list of hash algo supported.
-pub const SUPPORTED_HASHES: &str = "plain:sha256:sha512"; // XXX missing pbkdf2+sha256:pbkdf2+sha512
+pub const SUPPORTED_HASHES: &str = "plain:sha256:sha512:pbkdf2+sha256:pbkdf2+sha512";
 
 pub enum Algo<'a> {
     Plain,
     Sha { nonce: &'a str, size: &'a str },
-    // Pbkdf2Sha256 { nonce: &'a str, iterations: u32 },
-    // Pbkdf2Sha512 { nonce: &'a str, iterations: u32 },
+    Pbkdf2Sha256 { nonce: &'a str, iterations: u32 },
+    Pbkdf2Sha512 { nonce: &'a str, iterations: u32 },
 }
 
-impl<'a> std::convert::From<&'a Handshake> for Algo<'a> {
-    fn from(h: &'a Handshake) -> Self {
+impl<'a> std::convert::TryFrom<&'a Handshake> for Algo<'a> {
+    type Error = Error;
+
+    fn try_from(h: &'a Handshake) -> Result<Self, Error> {
+        let nonce = h.nonce.as_str();
+        let iterations = || match h.password_hash_iterations.parse() {
+            Ok(0) | Err(_) => Err(Error::ProtocolError("invalid password hash iterations")),
+            Ok(n) => Ok(n),
+        };
         match h.password_hash_algo.as_str() {
-            "plain" => Algo::Plain,
-            "sha256" | "sha512" => Algo::Sha {
-                nonce: h.nonce.as_str(),
-                size: if h.password_hash_algo.as_str() == "sha256" {
-                    "256"
-                } else {
-                    "512"
-                },
-            },
-            _ => unimplemented!(),
+            "plain" => Ok(Algo::Plain),
+            "sha256" => Ok(Algo::Sha { nonce, size: "256" }),
+            "sha512" => Ok(Algo::Sha { nonce, size: "512" }),
+            "pbkdf2+sha256" => Ok(Algo::Pbkdf2Sha256 {
+                nonce,
+                iterations: iterations()?,
+            }),
+            "pbkdf2+sha512" => Ok(Algo::Pbkdf2Sha512 {
+                nonce,
+                iterations: iterations()?,
+            }),
+            _ => Err(Error::ProtocolError("unsupported password hash algorithm")),
         }
     }
 }
 
 /// Create an authentication options chunk usable for init messages.
-pub fn create_auth(algo: Algo, password: &str) -> String {
+pub fn create_auth(algo: Algo, password: &str) -> Result<String, Error> {
     _create_auth(algo, password, openssl::rand::rand_bytes)
 }
 
 type _RandFunc = fn(&mut [u8]) -> Result<(), openssl::error::ErrorStack>;
 
-fn _create_auth(algo: Algo, password: &str, rand: _RandFunc) -> String {
+fn _create_auth(algo: Algo, password: &str, rand: _RandFunc) -> Result<String, Error> {
     match algo {
-        Algo::Plain => format!("password={}", password),
+        Algo::Plain => Ok(format!("password={}", password)),
         Algo::Sha { nonce, size } => {
+            let nonce_bytes = hex::decode(nonce).or(Err(Error::ProtocolError("invalid nonce")))?;
             let mut c_nonce = [0u8; 7];
-            rand(&mut c_nonce).unwrap();
+            rand(&mut c_nonce)?;
             let mut hasher = Hasher::new(if "256" == size {
                 MessageDigest::sha256()
             } else {
                 MessageDigest::sha512()
-            })
-            .unwrap();
+            })?;
             let hash = {
-                hasher
-                    .update(hex::decode(nonce).unwrap().as_slice())
-                    .unwrap();
-                hasher.update(&c_nonce).unwrap();
-                hasher.update(password.as_bytes()).unwrap();
-                hasher.finish().unwrap()
+                hasher.update(&nonce_bytes)?;
+                hasher.update(&c_nonce)?;
+                hasher.update(password.as_bytes())?;
+                hasher.finish()?
             };
 
-            format!(
+            Ok(format!(
                 "password_hash=sha{}:{}{}:{}",
                 size,
                 nonce,
                 hex::encode(c_nonce),
                 hex::encode(hash)
-            )
+            ))
+        }
+        Algo::Pbkdf2Sha256 { nonce, iterations } => {
+            create_pbkdf2(nonce, iterations, "256", password, rand)
+        }
+        Algo::Pbkdf2Sha512 { nonce, iterations } => {
+            create_pbkdf2(nonce, iterations, "512", password, rand)
         }
     }
 }
 
+fn create_pbkdf2(
+    nonce: &str,
+    iterations: u32,
+    size: &str,
+    password: &str,
+    rand: _RandFunc,
+) -> Result<String, Error> {
+    let mut salt = hex::decode(nonce).or(Err(Error::ProtocolError("invalid nonce")))?;
+    let mut c_nonce = [0u8; 7];
+    rand(&mut c_nonce)?;
+    salt.extend_from_slice(&c_nonce);
+    let (digest, mut hash) = if "256" == size {
+        (MessageDigest::sha256(), vec![0u8; 32])
+    } else {
+        (MessageDigest::sha512(), vec![0u8; 64])
+    };
+    openssl::pkcs5::pbkdf2_hmac(
+        password.as_bytes(),
+        &salt,
+        iterations as usize,
+        digest,
+        &mut hash,
+    )?;
+
+    Ok(format!(
+        "password_hash=pbkdf2+sha{}:{}:{}:{}",
+        size,
+        hex::encode(salt),
+        iterations,
+        hex::encode(hash)
+    ))
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use std::borrow::Borrow;
+    use std::convert::TryInto;
 
     fn handshake(algo: &'static str) -> Handshake {
         Handshake {
@@ -87,6 +135,11 @@ mod test {
             self.nonce = String::from(nonce);
             self
         }
+
+        fn with_iterations(mut self, iterations: &'static str) -> Self {
+            self.password_hash_iterations = String::from(iterations);
+            self
+        }
     }
 
     fn not_random(buf: &mut [u8]) -> Result<(), openssl::error::ErrorStack> {
@@ -96,8 +149,8 @@ mod test {
 
     #[test]
     fn test_auth_plain() {
-        let res = create_auth(handshake("plain").borrow().into(), "foobar");
-        assert_eq!("password=foobar", res)
+        let res = create_auth(handshake("plain").borrow().try_into().unwrap(), "foobar");
+        assert_eq!("password=foobar", res.unwrap())
     }
 
     #[test]
@@ -106,10 +159,12 @@ mod test {
             handshake("sha256")
                 .with_nonce("85b1ee00695a5b254e14f4885538df0d")
                 .borrow()
-                .into(),
+                .try_into()
+                .unwrap(),
             "test",
             not_random,
-        );
+        )
+        .unwrap();
         assert_eq!(
             "password_hash=sha256:85b1ee00695a5b254e14f4885538df0da4b73207f5aae4:\
              2c6ed12eb0109fca3aedc03bf03d9b6e804cd60a23e1731fd17794da423e21db",
@@ -123,14 +178,98 @@ mod test {
             handshake("sha512")
                 .with_nonce("85b1ee00695a5b254e14f4885538df0d")
                 .borrow()
-                .into(),
+                .try_into()
+                .unwrap(),
             "test",
             not_random,
-        );
+        )
+        .unwrap();
         let expected = "\
             password_hash=sha512:85b1ee00695a5b254e14f4885538df0da4b73207f5aae4:\
             0a1f0172a542916bd86e0cbceebc1c38ed791f6be246120452825f0d74ef1078c79e\
             9812de8b0ab3dfaf598b6ca14522374ec6a8653a46df3f96a6b54ac1f0f8";
         assert_eq!(expected, res)
     }
+
+    #[test]
+    fn test_pbkdf2_sha256() {
+        let res = _create_auth(
+            handshake("pbkdf2+sha256")
+                .with_nonce("85b1ee00695a5b254e14f4885538df0d")
+                .with_iterations("100000")
+                .borrow()
+                .try_into()
+                .unwrap(),
+            "test",
+            not_random,
+        )
+        .unwrap();
+        assert_eq!(
+            "password_hash=pbkdf2+sha256:85b1ee00695a5b254e14f4885538df0da4b73207f5aae4:\
+             100000:ba7facc3edb89cd06ae810e29ced85980ff36de2bb596fcf513aaab626876440",
+            res
+        )
+    }
+
+    #[test]
+    fn test_pbkdf2_sha512() {
+        let res = _create_auth(
+            handshake("pbkdf2+sha512")
+                .with_nonce("85b1ee00695a5b254e14f4885538df0d")
+                .with_iterations("100000")
+                .borrow()
+                .try_into()
+                .unwrap(),
+            "test",
+            not_random,
+        )
+        .unwrap();
+        let expected = "\
+            password_hash=pbkdf2+sha512:85b1ee00695a5b254e14f4885538df0da4b73207f5aae4:\
+            100000:5bd4b3d0c2a58bef25fe4f40b5170d3cff88b33ca9556d850ef275be4a387eaa122f\
+            f5a406798b84feb93886e41cd800206833ad86c196b9ab86e3738f13702d";
+        assert_eq!(expected, res)
+    }
+
+    #[test]
+    fn test_unsupported() {
+        let h = handshake("md5");
+        let res: Result<Algo, Error> = h.borrow().try_into();
+        assert!(res.is_err());
+        let h = handshake("pbkdf2+sha256");
+        let res: Result<Algo, Error> = h.borrow().try_into();
+        assert!(res.is_err(), "iterations are required");
+        let h = handshake("pbkdf2+sha512").with_iterations("0");
+        let res: Result<Algo, Error> = h.borrow().try_into();
+        assert!(res.is_err(), "iterations must be positive");
+    }
+
+    #[test]
+    fn test_sha_bad_nonce() {
+        let res = _create_auth(
+            handshake("sha256")
+                .with_nonce("not hex")
+                .borrow()
+                .try_into()
+                .unwrap(),
+            "test",
+            not_random,
+        );
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_pbkdf2_bad_nonce() {
+        let res = _create_auth(
+            handshake("pbkdf2+sha256")
+                .with_nonce("not hex")
+                .with_iterations("1000")
+                .borrow()
+                .try_into()
+                .unwrap(),
+            "test",
+            not_random,
+        );
+        assert!(res.is_err());
+    }
 }
@@ -0,0 +1,96 @@
+use super::link::MAX_FRAME_SIZE;
+use super::{Error, Result};
+use std::io::Read;
+
+/// Frame compression, as negotiated in the handshake and flagged after the
+/// message length.
+#[derive(Debug, PartialEq)]
+pub enum Compression {
+    Off,
+    Zlib,
+    Zstd,
+}
+
+impl std::convert::TryFrom<u8> for Compression {
+    type Error = Error;
+
+    fn try_from(flag: u8) -> Result<Self> {
+        match flag {
+            0 => Ok(Compression::Off),
+            1 => Ok(Compression::Zlib),
+            2 => Ok(Compression::Zstd),
+            _ => Err(Error::ProtocolError("unsupported frame compression")),
+        }
+    }
+}
+
+/// Decompress a frame body according to its compression flag.
+pub fn decompress(flag: u8, data: Vec<u8>) -> Result<Vec<u8>> {
+    use std::convert::TryInto;
+    match flag.try_into()? {
+        Compression::Off => Ok(data),
+        Compression::Zlib => read_bounded(flate2::read::ZlibDecoder::new(&data[..])),
+        Compression::Zstd => read_bounded(zstd::stream::read::Decoder::new(&data[..])?),
+    }
+}
+
+/// Read a decompressed frame, failing once it grows past `MAX_FRAME_SIZE`.
+fn read_bounded<R: Read>(decoder: R) -> Result<Vec<u8>> {
+    let mut out = vec![];
+    decoder
+        .take(MAX_FRAME_SIZE as u64 + 1)
+        .read_to_end(&mut out)?;
+    if out.len() > MAX_FRAME_SIZE {
+        return Err(Error::ProtocolError("decompressed message too long"));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_decompress_off() {
+        let data = b"\0\0\0\x04test".to_vec();
+        assert_eq!(data.clone(), decompress(0, data).unwrap());
+    }
+
+    #[test]
+    fn test_decompress_zlib() {
+        let data = b"\0\0\0\x04testinf\0\0\0\x07version\0\0\0\x032.9";
+        let mut enc = flate2::write::ZlibEncoder::new(vec![], flate2::Compression::default());
+        enc.write_all(data).unwrap();
+        let compressed = enc.finish().unwrap();
+        assert_eq!(data.to_vec(), decompress(1, compressed).unwrap());
+    }
+
+    #[test]
+    fn test_decompress_zstd() {
+        let data = b"\0\0\0\x04testinf\0\0\0\x07version\0\0\0\x032.9";
+        let compressed = zstd::stream::encode_all(&data[..], 3).unwrap();
+        assert_eq!(data.to_vec(), decompress(2, compressed).unwrap());
+    }
+
+    #[test]
+    fn test_decompress_corrupted() {
+        assert!(decompress(1, b"garbage".to_vec()).is_err());
+        assert!(decompress(2, b"garbage".to_vec()).is_err());
+    }
+
+    #[test]
+    fn test_decompress_too_long() {
+        let data = vec![0u8; MAX_FRAME_SIZE + 1];
+        let mut enc = flate2::write::ZlibEncoder::new(vec![], flate2::Compression::default());
+        enc.write_all(&data).unwrap();
+        assert!(decompress(1, enc.finish().unwrap()).is_err());
+        let compressed = zstd::stream::encode_all(&data[..], 3).unwrap();
+        assert!(decompress(2, compressed).is_err());
+    }
+
+    #[test]
+    fn test_decompress_unknown() {
+        assert!(decompress(42, vec![]).is_err());
+    }
+}
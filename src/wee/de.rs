@@ -24,6 +24,7 @@ pub enum Error {
     ExpectedTime,
     ExpectedInfo,
     ExpectedInfolist,
+    TooDeep,
     BadUTF8,
     TrailingCharacters,
 }
@@ -53,9 +54,14 @@ enum MsgPart<'de> {
     Data(&'de str),
 }
 
+/// Maximum nesting of containers, so crafted input can't overflow the stack.
+const MAX_DEPTH: usize = 32;
+
 pub struct DeMessage<'de> {
     input: &'de [u8],
     part: MsgPart<'de>,
+    // number of containers being decoded
+    depth: usize,
 }
 
 impl<'de> DeMessage<'de> {
@@ -63,30 +69,55 @@ impl<'de> DeMessage<'de> {
         DeMessage {
             input,
             part: MsgPart::Struct,
+            depth: 0,
         }
     }
 
+    /// Decode a container, keeping track of the nesting level.
+    fn nested<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        if self.depth >= MAX_DEPTH {
+            return Err(Error::TooDeep);
+        }
+        self.depth += 1;
+        let res = f(self);
+        self.depth -= 1;
+        res
+    }
+
+    /// Consume `len` bytes of input.
+    fn take(&mut self, len: usize) -> Result<&'de [u8]> {
+        if self.input.len() < len {
+            return Err(Error::Eof);
+        }
+        let (data, tail) = self.input.split_at(len);
+        self.input = tail;
+        Ok(data)
+    }
+
+    /// Look at the next `len` bytes of input, without consuming them.
+    fn peek(&self, len: usize) -> Result<&'de [u8]> {
+        self.input.get(..len).ok_or(Error::Eof)
+    }
+
     /// Read the 3byte data type marker.
     fn read_typ(&mut self) -> Result<&'de str> {
-        let typ = &self.input[0..3];
-        self.input = &self.input[3..];
-        std::str::from_utf8(typ).or_else(|_| Err(Error::ExpectedType))
+        let typ = self.take(3)?;
+        std::str::from_utf8(typ).or(Err(Error::ExpectedType))
+    }
+
+    /// Read a big endian 4 bytes integer.
+    fn read_u32(&mut self) -> Result<u32> {
+        let data = self.take(4)?;
+        Ok(u32::from_be_bytes(
+            data.try_into().or(Err(Error::ExpectedInteger))?,
+        ))
     }
 
     /// Read a wee byte array.
     fn read_buf(&mut self) -> Result<Option<&'de [u8]>> {
-        let (lenb, tail) = self.input.split_at(4);
-        let len = u32::from_be_bytes(lenb.try_into().or_else(|_| Err(Error::ExpectedInteger))?);
-        match len {
-            0xFFFFFFFF => {
-                self.input = tail;
-                Ok(None)
-            }
-            _ => {
-                let (data, tail) = tail.split_at(len as usize);
-                self.input = tail;
-                Ok(Some(data))
-            }
+        match self.read_u32()? {
+            0xFFFFFFFF => Ok(None),
+            len => Ok(Some(self.take(len as usize)?)),
         }
     }
 
@@ -98,11 +129,17 @@ impl<'de> DeMessage<'de> {
         }
     }
 
+    /// Return the type of the data being decoded.
+    fn data_typ(&self) -> Result<&'de str> {
+        match self.part {
+            MsgPart::Data(typ) => Ok(typ),
+            _ => Err(Error::Syntax),
+        }
+    }
+
     fn read_ptr(&mut self) -> Result<&'de str> {
-        let end = self.input[0] as usize + 1;
-        let val = std::str::from_utf8(&self.input[1..end]).or(Err(Error::BadUTF8))?;
-        self.input = &self.input[end..];
-        Ok(val)
+        let len = self.take(1)?[0] as usize;
+        std::str::from_utf8(self.take(len)?).or(Err(Error::BadUTF8))
     }
 }
 
@@ -154,41 +191,30 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut DeMessage<'de> {
                     Some(val) => visitor.visit_borrowed_bytes(val),
                     None => visitor.visit_none(),
                 },
-                "inf" => visitor.visit_seq(WeeSeq::new(self, "str", 2)),
-                "chr" => {
-                    let val = self.input[0];
-                    self.input = &self.input[1..];
-                    visitor.visit_i8(val as i8)
-                }
-                "int" => {
-                    let (val, tail) = self.input.split_at(4);
-                    self.input = tail;
-                    visitor.visit_i32(i32::from_be_bytes(val.try_into().unwrap()))
+                "inf" => visitor.visit_seq(WeeSeq::new(self, "str", 2)?),
+                "chr" => visitor.visit_i8(self.take(1)?[0] as i8),
+                "int" => visitor.visit_i32(self.read_u32()? as i32),
+                "lon" => {
+                    let val = self.read_ptr()?.parse().or(Err(Error::ExpectedLong))?;
+                    visitor.visit_i64(val)
                 }
-                "lon" | "ptr" | "tim" => {
-                    // XXX This may need revisiting, if any of those types are
-                    // used apart from just printing them.
-                    //
-                    // ptr may benefit from a "0x" prefix, if used.
-                    // tim is probably better as a number or struct
-                    // lon is probably better a number, if used in computation
-                    visitor.visit_borrowed_str(self.read_ptr()?)
+                "tim" => {
+                    let val = self.read_ptr()?.parse().or(Err(Error::ExpectedTime))?;
+                    visitor.visit_i64(val)
                 }
+                // hex digits, without "0x" prefix
+                "ptr" => visitor.visit_borrowed_str(self.read_ptr()?),
                 "arr" => {
                     let typ = self.read_typ()?;
-                    let (lenb, tail) = self.input.split_at(4);
-                    let len = u32::from_be_bytes(lenb.try_into().or(Err(Error::ExpectedInteger))?);
-                    self.input = tail;
-                    visitor.visit_seq(WeeSeq::new(self, typ, len as usize))
+                    let len = self.read_u32()?;
+                    self.nested(|de| visitor.visit_seq(WeeSeq::new(de, typ, len as usize)?))
                 }
                 "htb" => {
                     let ktyp = self.read_typ()?;
                     let vtyp = self.read_typ()?;
-                    let (lenb, tail) = self.input.split_at(4);
-                    let len = u32::from_be_bytes(lenb.try_into().or(Err(Error::ExpectedInteger))?);
-                    self.input = tail;
+                    let len = self.read_u32()?;
 
-                    visitor.visit_map(WeeMap::new(self, ktyp, vtyp, len as usize))
+                    self.nested(|de| visitor.visit_map(WeeMap::new(de, ktyp, vtyp, len as usize)?))
                 }
                 "hda" => {
                     let hpath = self.read_str()?.map_or_else(
@@ -196,25 +222,36 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut DeMessage<'de> {
                         |h| h.split("/").map(|h| format!("ptr_{}", h)).collect(),
                     );
                     let header = self.read_str()?;
-                    let key_types = header.map_or_else(
-                        || vec![],
-                        |h| {
-                            h.split(',')
-                                .map(|v| (&v[..v.len() - 4], &v[v.len() - 3..]))
-                                .collect()
-                        },
-                    );
-                    let (lenb, tail) = self.input.split_at(4);
-                    let len = u32::from_be_bytes(lenb.try_into().or(Err(Error::ExpectedInteger))?);
-                    self.input = tail;
-
-                    visitor.visit_seq(WeeHda::new(self, key_types, len as usize, hpath))
+                    let key_types = match header {
+                        Some(h) => h
+                            .split(',')
+                            .map(|v| {
+                                let mut kv = v.rsplitn(2, ':');
+                                match (kv.next(), kv.next()) {
+                                    (Some(typ), Some(key)) => Ok((key, typ)),
+                                    _ => Err(Error::ExpectedHdata),
+                                }
+                            })
+                            .collect::<Result<Vec<_>>>()?,
+                        None => vec![],
+                    };
+                    let len = self.read_u32()? as usize;
+                    // elements need keys, and each pointer and value takes at
+                    // least a byte, so the count is bounded by the input left.
+                    let min_size = hpath.len() + key_types.len();
+                    if len > 0
+                        && (key_types.is_empty() || len.saturating_mul(min_size) > self.input.len())
+                    {
+                        return Err(Error::ExpectedHdata);
+                    }
+
+                    self.nested(|de| visitor.visit_seq(WeeHda::new(de, key_types, len, hpath)?))
+                }
+                "inl" => self.nested(|de| visitor.visit_seq(WeeInl::new(de)?)),
+                _ => {
+                    trace!("decoding of wee type {:?} is not supported", typ);
+                    Err(Error::ExpectedType)
                 }
-                // XXX Infolist implementation (inl) is intentionally left out.
-                // According to the doc, infolist is just a less efficient
-                // request method from hda, so I'm leaving this unimplemented
-                // until there is a relevant use case for it.
-                _ => unimplemented!("decoding of wee type {:?}", typ),
             },
         }
     }
@@ -243,7 +280,7 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut DeMessage<'de> {
     {
         match self.part {
             MsgPart::Data("str") | MsgPart::Data("buf") => {
-                if self.input[0..4] == b"\xff\xff\xff\xff"[..] {
+                if self.peek(4)? == b"\xff\xff\xff\xff" {
                     self.input = &self.input[4..];
                     visitor.visit_none()
                 } else {
@@ -251,14 +288,15 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut DeMessage<'de> {
                 }
             }
             MsgPart::Data("ptr") => {
-                if self.input[0..2] == b"\x010"[..] {
+                if self.peek(2)? == b"\x010" {
                     self.input = &self.input[2..];
                     visitor.visit_none()
                 } else {
                     visitor.visit_some(self)
                 }
             }
-            _ => unimplemented!(),
+            // other types can't be null
+            _ => visitor.visit_some(self),
         }
     }
 
@@ -325,17 +363,14 @@ struct WeeSeq<'a, 'de: 'a> {
 }
 
 impl<'a, 'de> WeeSeq<'a, 'de> {
-    fn new(de: &'a mut DeMessage<'de>, typ: &'de str, count: usize) -> Self {
-        if let MsgPart::Data(parent_typ) = de.part {
-            de.part = MsgPart::Data(typ);
-            WeeSeq {
-                parent_typ,
-                de,
-                count,
-            }
-        } else {
-            unimplemented!()
-        }
+    fn new(de: &'a mut DeMessage<'de>, typ: &'de str, count: usize) -> Result<Self> {
+        let parent_typ = de.data_typ()?;
+        de.part = MsgPart::Data(typ);
+        Ok(WeeSeq {
+            parent_typ,
+            de,
+            count,
+        })
     }
 }
 
@@ -367,18 +402,19 @@ struct WeeMap<'a, 'de: 'a> {
 }
 
 impl<'a, 'de> WeeMap<'a, 'de> {
-    fn new(de: &'a mut DeMessage<'de>, ktyp: &'de str, vtyp: &'de str, count: usize) -> Self {
-        if let MsgPart::Data(parent_typ) = de.part {
-            WeeMap {
-                parent_typ,
-                de,
-                ktyp,
-                vtyp,
-                count,
-            }
-        } else {
-            unimplemented!()
-        }
+    fn new(
+        de: &'a mut DeMessage<'de>,
+        ktyp: &'de str,
+        vtyp: &'de str,
+        count: usize,
+    ) -> Result<Self> {
+        Ok(WeeMap {
+            parent_typ: de.data_typ()?,
+            de,
+            ktyp,
+            vtyp,
+            count,
+        })
     }
 }
 
@@ -429,20 +465,16 @@ impl<'a, 'de> WeeHda<'a, 'de> {
         key_types: Vec<(&'de str, &'de str)>,
         count: usize,
         hpath: Vec<String>,
-    ) -> Self {
-        if let MsgPart::Data(parent_typ) = de.part {
-            WeeHda {
-                parent_typ,
-                de,
-                key_types,
-                count,
-                kv_idx: 0,
-                ptr_idx: 0,
-                hpath,
-            }
-        } else {
-            unimplemented!()
-        }
+    ) -> Result<Self> {
+        Ok(WeeHda {
+            parent_typ: de.data_typ()?,
+            de,
+            key_types,
+            count,
+            kv_idx: 0,
+            ptr_idx: 0,
+            hpath,
+        })
     }
 }
 
@@ -512,7 +544,7 @@ impl<'a, 'de: 'a> de::Deserializer<'de> for &mut WeeHda<'a, 'de> {
     where
         V: Visitor<'de>,
     {
-        unimplemented!()
+        Err(Error::ExpectedHdata)
     }
 
     fn deserialize_identifier<V>(self, visitor: V) -> Result<V::Value>
@@ -562,18 +594,151 @@ impl<'a, 'de: 'a> de::Deserializer<'de> for &mut WeeHda<'a, 'de> {
     }
 }
 
-pub fn peek_str<'a>(buf: &'a [u8]) -> Result<Option<&'a str>> {
-    let (lenb, tail) = buf.split_at(4);
-    let len = u32::from_be_bytes(lenb.try_into().or(Err(Error::ExpectedInteger))?);
-    match len {
-        0xFFFFFFFF => Ok(None),
-        _ => {
-            let (data, _) = tail.split_at(len as usize);
-            Ok(Some(std::str::from_utf8(data).or(Err(Error::BadUTF8))?))
+/// Accessor for infolists, which are a (name, items) sequence.
+struct WeeInl<'a, 'de: 'a> {
+    parent_typ: &'de str,
+    de: &'a mut DeMessage<'de>,
+    // index of the next element: 0 for name, 1 for items
+    idx: usize,
+}
+
+impl<'a, 'de> WeeInl<'a, 'de> {
+    fn new(de: &'a mut DeMessage<'de>) -> Result<Self> {
+        Ok(WeeInl {
+            parent_typ: de.data_typ()?,
+            de,
+            idx: 0,
+        })
+    }
+}
+
+impl<'de, 'a> SeqAccess<'de> for WeeInl<'a, 'de> {
+    type Error = Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        self.idx += 1;
+        match self.idx {
+            1 => {
+                self.de.part = MsgPart::Data("str");
+                seed.deserialize(&mut *self.de).map(Some)
+            }
+            2 => {
+                let count = self.de.read_u32()?;
+                seed.deserialize(WeeInlItems {
+                    de: &mut *self.de,
+                    count: count as usize,
+                })
+                .map(Some)
+            }
+            _ => {
+                // reset parent
+                self.de.part = MsgPart::Data(self.parent_typ);
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Sequence of infolist items.
+struct WeeInlItems<'a, 'de: 'a> {
+    de: &'a mut DeMessage<'de>,
+    count: usize,
+}
+
+impl<'de, 'a> SeqAccess<'de> for WeeInlItems<'a, 'de> {
+    type Error = Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        if self.count == 0 {
+            return Ok(None);
+        }
+        self.count -= 1;
+        let count = self.de.read_u32()?;
+        seed.deserialize(WeeInlItem {
+            de: &mut *self.de,
+            count: count as usize,
+        })
+        .map(Some)
+    }
+}
+
+impl<'a, 'de: 'a> de::Deserializer<'de> for WeeInlItems<'a, 'de> {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_seq(self)
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+/// An infolist item, which is a map of named and typed variables.
+struct WeeInlItem<'a, 'de: 'a> {
+    de: &'a mut DeMessage<'de>,
+    // number of variables left to unpack
+    count: usize,
+}
+
+impl<'de, 'a> MapAccess<'de> for WeeInlItem<'a, 'de> {
+    type Error = Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        if self.count == 0 {
+            return Ok(None);
         }
+        self.count -= 1;
+        let name = self.de.read_str()?.ok_or(Error::ExpectedString)?;
+        seed.deserialize(de::value::BorrowedStrDeserializer::new(name))
+            .map(Some)
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let typ = self.de.read_typ()?;
+        self.de.part = MsgPart::Data(typ);
+        seed.deserialize(&mut *self.de)
     }
 }
 
+impl<'a, 'de: 'a> de::Deserializer<'de> for WeeInlItem<'a, 'de> {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_map(self)
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+pub fn peek_str<'a>(buf: &'a [u8]) -> Result<Option<&'a str>> {
+    DeMessage::from_bytes(buf).read_str()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -606,8 +771,8 @@ mod tests {
             i8,               // chr
             i32,              // int
             i32,              // int
-            &'a str,          // lon
-            &'a str,          // lon
+            i64,              // lon
+            i64,              // lon
             Option<&'a str>,  // str
             Option<&'a str>,  // str
             Option<&'a str>,  // str
@@ -615,7 +780,7 @@ mod tests {
             Option<&'a [u8]>, // buf
             Option<&'a str>,  // ptr
             Option<&'a str>,  // ptr
-            &'a str,          // tim
+            i64,              // tim
             (String, String), // arr
             (i32, i32, i32),  // arr
         );
@@ -630,8 +795,8 @@ mod tests {
             'A' as i8,
             123456,
             -123456,
-            "1234567890",
-            "-1234567890",
+            1234567890,
+            -1234567890,
             Some("a string"),
             Some(""),
             None,
@@ -639,13 +804,46 @@ mod tests {
             None,
             Some("1234abcd"),
             None,
-            "1321993456",
+            1321993456,
             (String::from("abc"), String::from("de")),
             (123, 456, 789),
         );
         assert_eq!(expected, from_bytes(encoded).unwrap());
     }
 
+    #[test]
+    fn test_deserialize_typed() {
+        use super::super::messages::Pointer;
+        use time::OffsetDateTime;
+
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Msg<'a>(
+            &'a str,
+            Pointer,
+            Option<Pointer>,
+            #[serde(deserialize_with = "super::super::messages::timestamp")] OffsetDateTime,
+        );
+        let encoded = b"\x00\x00\x00\x08test_msgptr\x081234abcdptr\x010tim\n1321993456";
+        let msg: Msg = from_bytes(encoded).unwrap();
+        assert_eq!(Pointer(0x1234abcd), msg.1);
+        assert_eq!("0x1234abcd", msg.1.to_string());
+        assert_eq!(None, msg.2);
+        assert_eq!(OffsetDateTime::from_unix_timestamp(1321993456), msg.3);
+
+        let encoded = b"\x00\x00\x00\x08test_msgptr\x04zzzzptr\x010tim\x011";
+        assert!(from_bytes::<Msg>(encoded).is_err());
+
+        // the supported years are -9999 to 9999.
+        let encoded = b"\x00\x00\x00\x08test_msgptr\x011ptr\x010tim\x0c253402300799";
+        assert_eq!(9999, from_bytes::<Msg>(encoded).unwrap().3.year());
+        let encoded = b"\x00\x00\x00\x08test_msgptr\x011ptr\x010tim\x0d-377705116800";
+        assert_eq!(-9999, from_bytes::<Msg>(encoded).unwrap().3.year());
+        let encoded = b"\x00\x00\x00\x08test_msgptr\x011ptr\x010tim\x0c253402300800";
+        assert!(from_bytes::<Msg>(encoded).is_err());
+        let encoded = b"\x00\x00\x00\x08test_msgptr\x011ptr\x010tim\x1199999999999999999";
+        assert!(from_bytes::<Msg>(encoded).is_err());
+    }
+
     // Hashtable can be deserialized
     #[test]
     fn test_deserialize_handshake() {
@@ -712,6 +910,159 @@ mod tests {
         assert_eq!(expected, from_bytes(encoded).unwrap());
     }
 
+    #[test]
+    fn test_deserialize_inl() {
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Server<'a> {
+            name: &'a str,
+            is_connected: i32,
+            nick: Option<&'a str>,
+            // skip buffer
+        }
+
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Msg<'a> {
+            id: &'a str,
+            inl: (&'a str, Vec<Server<'a>>),
+        }
+        let encoded = b"\0\0\0\x07serversinl\0\0\0\x0airc_server\0\0\0\x02\
+            \0\0\0\x03\0\0\0\x04namestr\0\0\0\x06libera\0\0\0\x0cis_connected\
+            int\0\0\0\x01\0\0\0\x04nickstr\0\0\0\x03bob\
+            \0\0\0\x04\0\0\0\x06bufferptr\x081234abcd\0\0\0\x04namestr\0\0\0\
+            \x04oftc\0\0\0\x04nickstr\xff\xff\xff\xff\0\0\0\x0cis_connected\
+            int\0\0\0\0";
+        let expected = Msg {
+            id: "servers",
+            inl: (
+                "irc_server",
+                vec![
+                    Server {
+                        name: "libera",
+                        is_connected: 1,
+                        nick: Some("bob"),
+                    },
+                    Server {
+                        name: "oftc",
+                        is_connected: 0,
+                        nick: None,
+                    },
+                ],
+            ),
+        };
+
+        assert_eq!(expected, from_bytes(encoded).unwrap());
+    }
+
+    #[test]
+    fn test_deserialize_inl_to_map() {
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Msg<'a> {
+            id: &'a str,
+            inl: (Option<&'a str>, Vec<HashMap<&'a str, &'a str>>),
+        }
+        let encoded = b"\0\0\0\x08channelsinl\0\0\0\x0birc_channel\0\0\0\x01\
+            \0\0\0\x02\0\0\0\x04namestr\0\0\0\x05#rust\0\0\0\x05topicstr\0\0\0\
+            \x03hi!";
+        let expected = Msg {
+            id: "channels",
+            inl: (
+                Some("irc_channel"),
+                vec![HashMap::from_iter(vec![
+                    ("name", "#rust"),
+                    ("topic", "hi!"),
+                ])],
+            ),
+        };
+        assert_eq!(expected, from_bytes(encoded).unwrap());
+
+        let empty = b"\0\0\0\x08channelsinl\xff\xff\xff\xff\0\0\0\0";
+        let expected = Msg {
+            id: "channels",
+            inl: (None, vec![]),
+        };
+        assert_eq!(expected, from_bytes(empty).unwrap());
+    }
+
+    #[test]
+    fn test_deserialize_truncated() {
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Hda<'a> {
+            ptr_bufs: &'a str,
+            number: i32,
+            full_name: Option<&'a str>,
+        }
+
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Msg<'a> {
+            id: &'a str,
+            hda: Vec<Hda<'a>>,
+        }
+        let encoded = b"\0\0\0\x07buffershda\0\0\0\x04bufs\0\0\0\x18number:int\
+            ,full_name:str\0\0\0\x02\x040123\0\0\0\x01\0\0\0\x0ccore.weechat\
+            \x03567\0\0\0\x02\0\0\0\x06potato";
+        assert!(from_bytes::<Msg>(encoded).is_ok());
+        for len in 0..encoded.len() {
+            assert!(
+                from_bytes::<Msg>(&encoded[..len]).is_err(),
+                "decoding {} bytes",
+                len
+            );
+        }
+        assert_eq!(Err(Error::Eof), from_bytes::<Msg>(&encoded[..40]));
+    }
+
+    #[test]
+    fn test_deserialize_malformed_hda() {
+        let encoded = b"\0\0\0\x07buffershda\0\0\0\x04bufs\0\0\0\x06number\0\0\0\0";
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Msg<'a> {
+            id: &'a str,
+            hda: Vec<HashMap<String, i32>>,
+        }
+        assert_eq!(Err(Error::ExpectedHdata), from_bytes::<Msg>(encoded));
+    }
+
+    #[test]
+    fn test_deserialize_hda_count() {
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Msg {
+            hda: Vec<HashMap<String, i32>>,
+        }
+        // without path nor keys, elements would take no input at all.
+        let encoded = b"\0\0\0\0hda\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff";
+        assert_eq!(Err(Error::ExpectedHdata), from_bytes::<Msg>(encoded));
+        let encoded = b"\0\0\0\0hda\xff\xff\xff\xff\xff\xff\xff\xff\0\0\0\0";
+        assert_eq!(Ok(Msg { hda: vec![] }), from_bytes::<Msg>(encoded));
+        // two elements of at least 2 bytes cannot fit in 3 bytes.
+        let encoded = b"\0\0\0\0hda\0\0\0\x03buf\0\0\0\x05n:int\0\0\0\x02\x011\0";
+        assert_eq!(Err(Error::ExpectedHdata), from_bytes::<Msg>(encoded));
+    }
+
+    #[test]
+    fn test_deserialize_nested_arr() {
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Msg<'a> {
+            id: &'a str,
+        }
+        let mut encoded = b"\0\0\0\x02idarr".to_vec();
+        for _ in 0..200_000 {
+            encoded.extend_from_slice(b"arr\0\0\0\x01");
+        }
+        encoded.extend_from_slice(b"int\0\0\0\0");
+        assert_eq!(Err(Error::TooDeep), from_bytes::<Msg>(&encoded));
+
+        // a few levels are fine
+        let encoded = b"\0\0\0\x02idarrarr\0\0\0\x01arr\0\0\0\x01int\0\0\0\0";
+        assert_eq!(Ok(Msg { id: "id" }), from_bytes::<Msg>(encoded));
+    }
+
+    #[test]
+    fn test_deserialize_unknown_type() {
+        let encoded = b"\0\0\0\x02idxyz\0\0\0\0";
+        let res: Result<HashMap<&str, &str>> = from_bytes(encoded);
+        assert_eq!(Err(Error::ExpectedType), res);
+    }
+
     /// Mostly useless test to verify decoding to map.
     /// It is only there to exercise the deserialization paths, as there
     /// are realistically no messages where values are of a single type.
@@ -724,7 +1075,7 @@ mod tests {
             // is formatted in to remove collisions.
             hda: Vec<HashMap<String, &'a str>>,
         };
-        let encoded = b"\0\0\0\x07buffershda\0\0\0\x04bufs\0\0\0\x18number:lon\
+        let encoded = b"\0\0\0\x07buffershda\0\0\0\x04bufs\0\0\0\x18number:ptr\
             ,full_name:str\0\0\0\x02\x040123\x011\0\0\0\x0ccore.weechat\
             \x03567\x012\0\0\0\x06potato";
         let expected = Msg {
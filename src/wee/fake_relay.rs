@@ -0,0 +1,1133 @@
+//! Stand-in for a weechat relay, for testing the client end to end.
+//!
+//! It listens on a local TCP port or UNIX socket, authenticates with the
+//! configured hash algorithm, and answers the few commands the client sends
+//! with frames built by the serializer.
+use super::ser::{to_frame, Typed};
+use async_channel::{Receiver, Sender};
+use openssl::hash::{Hasher, MessageDigest};
+use serde::Serialize;
+use smol::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use smol::net::unix::UnixListener;
+use smol::net::TcpListener;
+use smol::Task;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const NONCE: &str = "85b1ee00695a5b254e14f4885538df0d";
+const ITERATIONS: u32 = 1000;
+/// Time to wait for the client before failing a test.
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct Settings {
+    /// Hash algorithm required for authentication.
+    pub algo: &'static str,
+    pub password: &'static str,
+    /// Base32 TOTP secret, enabling TOTP when set.
+    pub totp_secret: Option<&'static str>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            algo: "pbkdf2+sha512",
+            password: "secret",
+            totp_secret: None,
+        }
+    }
+}
+
+struct FakeBuffer {
+    ptr: String,
+    full_name: String,
+    short_name: String,
+    title: String,
+    lines: Vec<FakeLine>,
+}
+
+#[derive(Clone)]
+struct FakeLine {
+    ptr: String,
+    date: i64,
+    prefix: String,
+    message: String,
+    tags: Vec<String>,
+}
+
+/// Relay state shared with the server task.
+struct State {
+    buffers: Vec<FakeBuffer>,
+    next_ptr: u64,
+    /// Queue of frames to the connected client.
+    client: Option<Sender<Vec<u8>>>,
+    /// Whether buffer list changes are sent.
+    synced: bool,
+    /// Names of the buffers whose lines are sent, "*" for all.
+    synced_buffers: HashSet<String>,
+}
+
+impl State {
+    fn new() -> Self {
+        let mut state = State {
+            buffers: vec![],
+            next_ptr: 0x1000,
+            client: None,
+            synced: false,
+            synced_buffers: HashSet::new(),
+        };
+        for (full_name, short_name, title) in [
+            ("core.weechat", "weechat", "WeeChat"),
+            ("irc.libera.#rust", "#rust", "Rust \x1904lang"),
+        ]
+        .iter()
+        {
+            let ptr = state.new_ptr();
+            state.buffers.push(FakeBuffer {
+                ptr,
+                full_name: String::from(*full_name),
+                short_name: String::from(*short_name),
+                title: String::from(*title),
+                lines: vec![],
+            });
+            for i in 0..2 {
+                state.add_line(
+                    state.buffers.len() - 1,
+                    "--",
+                    &format!("{} backlog {}", short_name, i),
+                );
+            }
+        }
+        state
+    }
+
+    fn new_ptr(&mut self) -> String {
+        self.next_ptr += 0x10;
+        format!("{:x}", self.next_ptr)
+    }
+
+    fn buffer(&self, name: &str) -> Option<usize> {
+        let ptr = name.trim_start_matches("0x");
+        self.buffers
+            .iter()
+            .position(|b| b.full_name == name || b.ptr == ptr)
+    }
+
+    /// Add a line to a buffer, and send it to the client once synced.
+    fn add_line(&mut self, buffer: usize, prefix: &str, message: &str) {
+        self.add_tagged_line(buffer, prefix, message, &["irc_privmsg"]);
+    }
+
+    fn add_tagged_line(&mut self, buffer: usize, prefix: &str, message: &str, tags: &[&str]) {
+        let line = FakeLine {
+            ptr: self.new_ptr(),
+            date: 1_600_000_000 + self.next_ptr as i64,
+            prefix: String::from(prefix),
+            message: String::from(message),
+            tags: tags.iter().map(|t| String::from(*t)).collect(),
+        };
+        self.buffers[buffer].lines.push(line.clone());
+        let name = &self.buffers[buffer].full_name;
+        if !self.synced_buffers.contains("*") && !self.synced_buffers.contains(name) {
+            return;
+        }
+        let event = Hdata {
+            id: String::from("_buffer_line_added"),
+            hda: vec![LineItem::new(&self.buffers[buffer].ptr, &line, false)],
+        };
+        self.send(&event);
+    }
+
+    fn buffer_item(&self, buffer: usize) -> BufferItem {
+        let b = &self.buffers[buffer];
+        BufferItem {
+            ptr_buffer: b.ptr.clone(),
+            number: buffer as i32 + 1,
+            short_name: b.short_name.clone(),
+            full_name: b.full_name.clone(),
+            title: b.title.clone(),
+            nicklist_display_groups: 1,
+        }
+    }
+
+    /// Send a `_buffer_*` event with the usual fields, once synced.
+    fn buffer_event(&self, id: &str, buffer: usize) {
+        if !self.synced {
+            return;
+        }
+        let b = &self.buffers[buffer];
+        self.send(&Hdata {
+            id: String::from(id),
+            hda: vec![BufferEventItem {
+                ptr_buffer: b.ptr.clone(),
+                number: buffer as i32 + 1,
+                full_name: b.full_name.clone(),
+                short_name: b.short_name.clone(),
+            }],
+        });
+    }
+
+    fn send<T: Serialize>(&self, msg: &T) {
+        if let Some(ref client) = self.client {
+            let _ = client.try_send(to_frame(msg).expect("serializing fake relay message"));
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Hdata<T> {
+    id: String,
+    hda: Vec<T>,
+}
+
+#[derive(Serialize)]
+struct BufferItem {
+    ptr_buffer: String,
+    number: i32,
+    short_name: String,
+    full_name: String,
+    title: String,
+    nicklist_display_groups: i32,
+}
+
+#[derive(Serialize)]
+struct BufferEventItem {
+    ptr_buffer: String,
+    number: i32,
+    full_name: String,
+    short_name: String,
+}
+
+#[derive(Serialize)]
+struct HotlistItem {
+    ptr_hotlist: String,
+    priority: i32,
+    buffer: Typed<String>,
+    count: (i32, i32, i32, i32),
+}
+
+#[derive(Serialize)]
+struct LineItem {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ptr_buffer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ptr_line: Option<String>,
+    ptr_line_data: String,
+    buffer: Typed<String>,
+    date: Typed<i64>,
+    displayed: i8,
+    highlight: i8,
+    prefix: String,
+    message: String,
+    notify_level: i8,
+    tags_array: Vec<String>,
+}
+
+impl LineItem {
+    /// Line hdata, from the buffer own lines when `in_buffer`, or from a
+    /// line added event.
+    fn new(buffer: &str, line: &FakeLine, in_buffer: bool) -> Self {
+        LineItem {
+            ptr_buffer: if in_buffer {
+                Some(String::from(buffer))
+            } else {
+                None
+            },
+            ptr_line: if in_buffer {
+                Some(format!("{}0", line.ptr))
+            } else {
+                None
+            },
+            ptr_line_data: line.ptr.clone(),
+            buffer: Typed("ptr", String::from(buffer)),
+            date: Typed("tim", line.date),
+            displayed: 1,
+            highlight: 0,
+            prefix: line.prefix.clone(),
+            message: line.message.clone(),
+            notify_level: 1,
+            tags_array: line.tags.clone(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct CompletionItem {
+    ptr_completion: String,
+    context: String,
+    base_word: String,
+    pos_start: i32,
+    pos_end: i32,
+    add_space: i8,
+    list: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct Pong {
+    id: String,
+    str: String,
+}
+
+#[derive(Serialize)]
+struct Info {
+    id: String,
+    inf: (String, String),
+}
+
+#[derive(Serialize)]
+struct Handshake {
+    id: String,
+    htb: BTreeMap<String, String>,
+}
+
+/// Listening socket of the relay.
+enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+/// Running fake relay.
+pub struct FakeRelay {
+    /// Client configuration of the relay address.
+    address: String,
+    state: Arc<Mutex<State>>,
+    /// Commands received after authentication.
+    commands: Receiver<String>,
+    _server: Task<()>,
+}
+
+impl FakeRelay {
+    pub async fn start(settings: Settings) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let address = format!("host = '127.0.0.1'\nport = {}\n", port);
+        FakeRelay::listen(Listener::Tcp(listener), address, settings)
+    }
+
+    /// Start a relay listening on a UNIX socket at `path`.
+    pub async fn start_unix(settings: Settings, path: &Path) -> Self {
+        let listener = UnixListener::bind(path).unwrap();
+        let address = format!("socket_path = '{}'\n", path.display());
+        FakeRelay::listen(Listener::Unix(listener), address, settings)
+    }
+
+    fn listen(listener: Listener, address: String, settings: Settings) -> Self {
+        let state = Arc::new(Mutex::new(State::new()));
+        let (sender, commands) = async_channel::unbounded();
+        let server = smol::spawn(serve(listener, settings, state.clone(), sender));
+        FakeRelay {
+            address,
+            state,
+            commands,
+            _server: server,
+        }
+    }
+
+    /// Client configuration for connecting to this relay.
+    pub fn conf(&self, password: &str, totp_secret: Option<&str>) -> crate::config::Conf {
+        let mut conf = format!("{}password = '{}'\n", self.address, password);
+        if let Some(secret) = totp_secret {
+            conf.push_str(&format!("totp_secret = '{}'\n", secret));
+        }
+        toml::from_str(&conf).unwrap()
+    }
+
+    /// Add a line to a buffer, as if someone talked.
+    pub fn push_line(&self, full_name: &str, prefix: &str, message: &str) {
+        let mut state = self.state.lock().unwrap();
+        let idx = state.buffer(full_name).expect("unknown fake buffer");
+        state.add_line(idx, prefix, message);
+    }
+
+    /// Open a buffer after the others.
+    pub fn open_buffer(&self, full_name: &str, short_name: &str) {
+        let mut state = self.state.lock().unwrap();
+        let ptr = state.new_ptr();
+        state.buffers.push(FakeBuffer {
+            ptr,
+            full_name: String::from(full_name),
+            short_name: String::from(short_name),
+            title: String::new(),
+            lines: vec![],
+        });
+        let item = state.buffer_item(state.buffers.len() - 1);
+        if state.synced {
+            state.send(&Hdata {
+                id: String::from("_buffer_opened"),
+                hda: vec![item],
+            });
+        }
+    }
+
+    pub fn close_buffer(&self, full_name: &str) {
+        let mut state = self.state.lock().unwrap();
+        let idx = state.buffer(full_name).expect("unknown fake buffer");
+        state.buffer_event("_buffer_closing", idx);
+        state.buffers.remove(idx);
+    }
+
+    pub fn rename_buffer(&self, full_name: &str, new_name: &str) {
+        let mut state = self.state.lock().unwrap();
+        let idx = state.buffer(full_name).expect("unknown fake buffer");
+        state.buffers[idx].full_name = String::from(new_name);
+        state.buffer_event("_buffer_renamed", idx);
+    }
+
+    /// Move a buffer to `number`, shifting the others.
+    pub fn move_buffer(&self, full_name: &str, number: usize) {
+        let mut state = self.state.lock().unwrap();
+        let idx = state.buffer(full_name).expect("unknown fake buffer");
+        let buffer = state.buffers.remove(idx);
+        state.buffers.insert(number - 1, buffer);
+        state.buffer_event("_buffer_moved", number - 1);
+    }
+
+    /// Run /upgrade, after which all objects have new pointers.
+    pub fn upgrade(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.send(&Hdata::<LineItem> {
+            id: String::from("_upgrade"),
+            hda: vec![],
+        });
+        for b in 0..state.buffers.len() {
+            state.buffers[b].ptr = state.new_ptr();
+            for l in 0..state.buffers[b].lines.len() {
+                state.buffers[b].lines[l].ptr = state.new_ptr();
+            }
+        }
+        state.send(&Hdata::<LineItem> {
+            id: String::from("_upgrade_ended"),
+            hda: vec![],
+        });
+    }
+
+    /// Add a line with tags, e.g. a join.
+    pub fn push_tagged_line(&self, full_name: &str, prefix: &str, message: &str, tags: &[&str]) {
+        let mut state = self.state.lock().unwrap();
+        let idx = state.buffer(full_name).expect("unknown fake buffer");
+        state.add_tagged_line(idx, prefix, message, tags);
+    }
+
+    /// Wait for a command starting with `prefix`, skipping others.
+    pub async fn expect_command(&self, prefix: &str) -> String {
+        let wait = async {
+            loop {
+                let command = self.commands.recv().await.expect("relay stopped");
+                if command.starts_with(prefix) {
+                    return command;
+                }
+            }
+        };
+        smol::future::or(wait, async {
+            smol::Timer::after(TIMEOUT).await;
+            panic!("timed out waiting for command {:?}", prefix)
+        })
+        .await
+    }
+}
+
+/// Accept connections, one at a time.
+async fn serve(
+    listener: Listener,
+    settings: Settings,
+    state: Arc<Mutex<State>>,
+    commands: Sender<String>,
+) {
+    loop {
+        match &listener {
+            Listener::Tcp(l) => match l.accept().await {
+                Ok((stream, _)) => serve_client(stream, &settings, &state, &commands).await,
+                Err(_) => return,
+            },
+            Listener::Unix(l) => match l.accept().await {
+                Ok((stream, _)) => serve_client(stream, &settings, &state, &commands).await,
+                Err(_) => return,
+            },
+        }
+    }
+}
+
+async fn serve_client<S>(
+    stream: S,
+    settings: &Settings,
+    state: &Mutex<State>,
+    commands: &Sender<String>,
+) where
+    S: AsyncRead + AsyncWrite + Clone + Unpin + Send + 'static,
+{
+    let (client, frames) = async_channel::unbounded();
+    let writer = smol::spawn(write_frames(stream.clone(), frames));
+    state.lock().unwrap().client = Some(client);
+    handle(stream, settings, state, commands).await;
+    {
+        let mut state = state.lock().unwrap();
+        state.client = None;
+        state.synced = false;
+        state.synced_buffers.clear();
+    }
+    // send what is left, then drop the connection.
+    writer.await;
+}
+
+async fn write_frames<S>(mut stream: S, frames: Receiver<Vec<u8>>)
+where
+    S: AsyncWrite + Unpin,
+{
+    while let Ok(frame) = frames.recv().await {
+        if stream.write_all(&frame).await.is_err() {
+            return;
+        }
+    }
+    let _ = stream.flush().await;
+}
+
+/// Split a "(id) command args" line.
+fn parse_command(line: &str) -> (String, &str) {
+    match line.strip_prefix('(').and_then(|l| l.split_once(')')) {
+        Some((id, command)) => (String::from(id), command.trim()),
+        None => (String::new(), line.trim()),
+    }
+}
+
+/// Parse "key=value,key=value" options.
+fn parse_options(options: &str) -> HashMap<&str, &str> {
+    options
+        .split(',')
+        .filter_map(|o| o.split_once('='))
+        .collect()
+}
+
+/// Serve a connection until the client quits, disconnects, or fails to
+/// authenticate.
+async fn handle<S>(stream: S, settings: &Settings, state: &Mutex<State>, commands: &Sender<String>)
+where
+    S: AsyncRead + Unpin,
+{
+    let mut reader = BufReader::new(stream);
+    let mut authenticated = false;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        match reader.read_line(&mut line).await {
+            Ok(0) | Err(_) => return,
+            Ok(_) => (),
+        }
+        let (id, command) = parse_command(&line);
+        let (name, args) = command.split_once(' ').unwrap_or((command, ""));
+        let mut state = state.lock().unwrap();
+        match (authenticated, name) {
+            (_, "handshake") => {
+                let options = parse_options(args);
+                let offered: Vec<&str> = options
+                    .get("password_hash_algo")
+                    .map_or(vec!["plain"], |a| a.split(':').collect());
+                let algo = if offered.contains(&settings.algo) {
+                    settings.algo
+                } else {
+                    ""
+                };
+                let totp = if settings.totp_secret.is_some() {
+                    "on"
+                } else {
+                    "off"
+                };
+                let iterations = ITERATIONS.to_string();
+                let htb = vec![
+                    ("password_hash_algo", algo),
+                    ("password_hash_iterations", iterations.as_str()),
+                    ("totp", totp),
+                    ("nonce", NONCE),
+                    ("compression", "off"),
+                ]
+                .into_iter()
+                .map(|(k, v)| (String::from(k), String::from(v)))
+                .collect();
+                state.send(&Handshake {
+                    id: String::from("handshake"),
+                    htb,
+                });
+            }
+            (false, "init") => {
+                if !check_init(settings, &parse_options(args)) {
+                    return;
+                }
+                authenticated = true;
+            }
+            // the relay ignores anything else until authenticated.
+            (false, _) => (),
+            (true, "quit") => return,
+            (true, _) => {
+                let _ = commands.try_send(String::from(command));
+                answer(&id, name, args, &mut state);
+            }
+        }
+    }
+}
+
+/// Check the password and TOTP code of an init command.
+fn check_init(settings: &Settings, options: &HashMap<&str, &str>) -> bool {
+    if let Some(secret) = settings.totp_secret {
+        let expected = super::totp::generate(secret).unwrap();
+        if options.get("totp") != Some(&expected.as_str()) {
+            return false;
+        }
+    }
+    let password = settings.password.as_bytes();
+    if let Some(plain) = options.get("password") {
+        return settings.algo == "plain" && plain.as_bytes() == password;
+    }
+    let hash = match options.get("password_hash") {
+        Some(hash) => hash.split(':').collect::<Vec<_>>(),
+        None => return false,
+    };
+    let digest = |algo: &str| match algo {
+        "sha256" | "pbkdf2+sha256" => MessageDigest::sha256(),
+        _ => MessageDigest::sha512(),
+    };
+    match hash[..] {
+        [algo, salt, expected] if algo == settings.algo && algo.starts_with("sha") => {
+            if !salt.starts_with(NONCE) {
+                return false;
+            }
+            let mut hasher = Hasher::new(digest(algo)).unwrap();
+            hasher.update(&hex::decode(salt).unwrap()).unwrap();
+            hasher.update(password).unwrap();
+            hex::encode(hasher.finish().unwrap()) == expected
+        }
+        [algo, salt, iterations, expected] if algo == settings.algo => {
+            if !salt.starts_with(NONCE) || iterations != ITERATIONS.to_string() {
+                return false;
+            }
+            let digest = digest(algo);
+            let mut hash = vec![0u8; digest.size()];
+            openssl::pkcs5::pbkdf2_hmac(
+                password,
+                &hex::decode(salt).unwrap(),
+                ITERATIONS as usize,
+                digest,
+                &mut hash,
+            )
+            .unwrap();
+            hex::encode(hash) == expected
+        }
+        _ => false,
+    }
+}
+
+/// Answer an authenticated command.
+fn answer(id: &str, name: &str, args: &str, state: &mut State) {
+    let id = String::from(id);
+    match name {
+        "info" if args == "version" => state.send(&Info {
+            id,
+            inf: (String::from("version"), String::from("3.0")),
+        }),
+        "hdata" if args == "buffer:gui_buffers(*)" => {
+            let hda = (0..state.buffers.len())
+                .map(|i| state.buffer_item(i))
+                .collect();
+            state.send(&Hdata { id, hda });
+        }
+        "hdata" if args == "hotlist:gui_hotlist(*)" => {
+            let hda: Vec<HotlistItem> = state
+                .buffers
+                .iter()
+                .skip(1)
+                .map(|b| HotlistItem {
+                    ptr_hotlist: format!("{}1", b.ptr),
+                    priority: 1,
+                    buffer: Typed("ptr", b.ptr.clone()),
+                    count: (0, b.lines.len() as i32, 0, 0),
+                })
+                .collect();
+            state.send(&Hdata { id, hda });
+        }
+        "hdata" if args.starts_with("buffer:0x") => {
+            // buffer:0x<ptr>/own_lines/last_line(-<count>)/data
+            let ptr = &args["buffer:".len()..args.find('/').unwrap_or(args.len())];
+            let count: usize = args
+                .split("(-")
+                .nth(1)
+                .and_then(|c| c.split(')').next())
+                .and_then(|c| c.parse().ok())
+                .unwrap_or(1);
+            let hda: Vec<LineItem> = match state.buffer(ptr) {
+                Some(idx) => {
+                    let b = &state.buffers[idx];
+                    b.lines
+                        .iter()
+                        .rev()
+                        .take(count)
+                        .map(|l| LineItem::new(&b.ptr, l, true))
+                        .collect()
+                }
+                None => vec![],
+            };
+            state.send(&Hdata { id, hda });
+        }
+        "hdata" | "nicklist" => state.send(&Hdata::<LineItem> { id, hda: vec![] }),
+        "completion" => {
+            // completion 0x<ptr> <pos> <data>
+            let mut parts = args.splitn(3, ' ');
+            let (_, pos, data) = (parts.next(), parts.next(), parts.next().unwrap_or(""));
+            let pos = pos.and_then(|p| p.parse().ok()).unwrap_or(data.len());
+            let start = data[..pos].rfind(' ').map_or(0, |i| i + 1);
+            let base_word = &data[start..pos];
+            let list = ["alice", "alicia", "bob"]
+                .iter()
+                .filter(|n| n.starts_with(base_word))
+                .map(|n| String::from(*n))
+                .collect();
+            state.send(&Hdata {
+                id,
+                hda: vec![CompletionItem {
+                    ptr_completion: String::from("abcd"),
+                    context: String::from("auto"),
+                    base_word: String::from(base_word),
+                    pos_start: start as i32,
+                    pos_end: pos as i32 - 1,
+                    add_space: 1,
+                    list,
+                }],
+            });
+        }
+        "ping" => state.send(&Pong {
+            id: String::from("_pong"),
+            str: String::from(args),
+        }),
+        "sync" | "desync" => {
+            // [buffer,... [option,...]], all buffers and options by default.
+            let mut args = args.split(' ');
+            let names = args.next().filter(|n| !n.is_empty()).unwrap_or("*");
+            let options = args.next().unwrap_or("buffers,buffer");
+            let add = name == "sync";
+            if names == "*" && options.contains("buffers") {
+                state.synced = add;
+            }
+            if names != "*" || options.split(',').any(|o| o == "buffer") {
+                for name in names.split(',') {
+                    if add {
+                        state.synced_buffers.insert(String::from(name));
+                    } else {
+                        state.synced_buffers.remove(name);
+                    }
+                }
+            }
+        }
+        "input" => {
+            // input <buffer> <text>, echoed like a message from the user.
+            let (buffer, text) = args.split_once(' ').unwrap_or((args, ""));
+            match state.buffer(buffer) {
+                Some(idx) if !text.starts_with('/') => state.add_line(idx, "me", text),
+                _ => (),
+            }
+        }
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Error, Wee};
+    use super::*;
+    use futures::{select, FutureExt};
+
+    fn no_prompt() -> std::io::Result<String> {
+        panic!("unexpected TOTP prompt")
+    }
+
+    /// Handle messages until `done` is true.
+    async fn run_until(wee: &mut Wee, done: impl Fn(&Wee) -> bool) {
+        let run = async {
+            while !done(wee) {
+                wee.run().await.unwrap();
+            }
+        };
+        smol::future::or(run, async {
+            smol::Timer::after(TIMEOUT).await;
+            panic!("timed out waiting for the relay")
+        })
+        .await
+    }
+
+    fn messages(wee: &Wee) -> Vec<&str> {
+        wee.get_lines().iter().map(|l| l.message.as_str()).collect()
+    }
+
+    #[test]
+    fn test_auth_algorithms() {
+        smol::block_on(async {
+            for algo in auth_algos().iter() {
+                let relay = FakeRelay::start(Settings {
+                    algo,
+                    ..Settings::default()
+                })
+                .await;
+                let wee = Wee::connect(&relay.conf("secret", None), no_prompt)
+                    .await
+                    .unwrap_or_else(|e| panic!("connecting with {}: {}", algo, e));
+                wee.close().await.unwrap();
+            }
+        });
+    }
+
+    fn auth_algos() -> Vec<&'static str> {
+        super::super::auth::SUPPORTED_HASHES.split(':').collect()
+    }
+
+    #[test]
+    fn test_auth_failure() {
+        smol::block_on(async {
+            let relay = FakeRelay::start(Settings::default()).await;
+            match Wee::connect(&relay.conf("wrong", None), no_prompt).await {
+                Err(Error::ProtocolError(msg)) => assert!(msg.contains("Check password")),
+                Err(e) => panic!("unexpected error {}", e),
+                Ok(_) => panic!("authenticated with a wrong password"),
+            }
+        });
+    }
+
+    #[test]
+    fn test_auth_totp() {
+        smol::block_on(async {
+            let secret = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+            let relay = FakeRelay::start(Settings {
+                totp_secret: Some(secret),
+                ..Settings::default()
+            })
+            .await;
+            let wee = Wee::connect(&relay.conf("secret", Some(secret)), no_prompt)
+                .await
+                .unwrap();
+            wee.close().await.unwrap();
+
+            // codes are not prompted for when reconnecting.
+            let (lost, _) = smol::net::unix::UnixStream::pair().unwrap();
+            let mut wee = Wee::new(Box::new(lost));
+            match wee.reconnect(&relay.conf("secret", None)).await {
+                Err(Error::TotpRequired) => (),
+                res => panic!("unexpected result {:?}", res),
+            }
+            wee.reconnect(&relay.conf("secret", Some(secret)))
+                .await
+                .unwrap();
+            wee.close().await.unwrap();
+        });
+    }
+
+    #[test]
+    fn test_connect_sync_input() {
+        smol::block_on(async {
+            let relay = FakeRelay::start(Settings::default()).await;
+            let mut wee = Wee::connect(&relay.conf("secret", None), no_prompt)
+                .await
+                .unwrap();
+            wee.sync().await.unwrap();
+            assert_eq!("sync *", relay.expect_command("sync").await);
+            let names: Vec<&str> = wee
+                .get_buffers()
+                .iter()
+                .map(|b| b.full_name.as_str())
+                .collect();
+            assert_eq!(vec!["core.weechat", "irc.libera.#rust"], names);
+            assert_eq!("core.weechat", wee.get_current_buffer().unwrap().full_name);
+            run_until(&mut wee, |w| w.get_lines().len() == 2).await;
+            assert_eq!(
+                vec!["weechat backlog 0", "weechat backlog 1"],
+                messages(&wee)
+            );
+
+            // lines of other buffers are counted in the hotlist.
+            run_until(&mut wee, |w| w.get_buffers()[1].hotlist.1 == 2).await;
+            relay.push_line("irc.libera.#rust", "alice", "hi");
+            run_until(&mut wee, |w| w.get_buffers()[1].hotlist.1 == 3).await;
+
+            wee.switch_current_buffer(&String::from("irc.libera.#rust"))
+                .await
+                .unwrap();
+            run_until(&mut wee, |w| w.get_lines().len() == 3).await;
+            assert_eq!(
+                vec!["#rust backlog 0", "#rust backlog 1", "hi"],
+                messages(&wee)
+            );
+            // the nicklist is empty, but known to be the one of the buffer.
+            run_until(&mut wee, |w| w.get_nicklist().is_some()).await;
+
+            let completion = wee.complete(3, "ali");
+            let mut completion = Box::pin(completion.fuse());
+            let completed = loop {
+                select! {
+                    res = completion => break res.unwrap().unwrap(),
+                    res = wee.run().fuse() => res.unwrap(),
+                }
+            };
+            assert_eq!(vec!["alice", "alicia"], completed.list);
+
+            wee.send("", "input irc.libera.#rust hello").await.unwrap();
+            relay.expect_command("input irc.libera.#rust hello").await;
+            run_until(&mut wee, |w| w.get_lines().len() == 4).await;
+            assert_eq!(Some(&"hello"), messages(&wee).last());
+
+            let tags = ["irc_join", "irc_smart_filter"];
+            relay.push_tagged_line("irc.libera.#rust", "-->", "alice joined", &tags);
+            run_until(&mut wee, |w| w.get_lines().len() == 5).await;
+            assert_eq!(tags.to_vec(), wee.get_lines()[4].tags_array);
+            assert_eq!(vec!["irc_privmsg"], wee.get_lines()[0].tags_array);
+
+            wee.close().await.unwrap();
+        });
+    }
+
+    #[test]
+    fn test_buffer_events() {
+        smol::block_on(async {
+            let relay = FakeRelay::start(Settings::default()).await;
+            let mut wee = Wee::connect(&relay.conf("secret", None), no_prompt)
+                .await
+                .unwrap();
+            wee.sync().await.unwrap();
+            assert_eq!("sync *", relay.expect_command("sync").await);
+            let buffers = |w: &Wee| -> Vec<(i32, String)> {
+                w.get_buffers()
+                    .iter()
+                    .map(|b| (b.number, b.full_name.clone()))
+                    .collect()
+            };
+            let expected = |names: &[&str]| -> Vec<(i32, String)> {
+                names
+                    .iter()
+                    .enumerate()
+                    .map(|(i, n)| (i as i32 + 1, String::from(*n)))
+                    .collect()
+            };
+
+            relay.open_buffer("irc.libera.#weechat", "#weechat");
+            relay.open_buffer("irc.libera.alice", "alice");
+            let names = [
+                "core.weechat",
+                "irc.libera.#rust",
+                "irc.libera.#weechat",
+                "irc.libera.alice",
+            ];
+            run_until(&mut wee, |w| buffers(w) == expected(&names)).await;
+
+            relay.move_buffer("irc.libera.alice", 2);
+            let names = [
+                "core.weechat",
+                "irc.libera.alice",
+                "irc.libera.#rust",
+                "irc.libera.#weechat",
+            ];
+            run_until(&mut wee, |w| buffers(w) == expected(&names)).await;
+
+            // the current buffer follows renames.
+            wee.switch_current_buffer(&String::from("irc.libera.alice"))
+                .await
+                .unwrap();
+            relay.rename_buffer("irc.libera.alice", "irc.libera.alice2");
+            let names = [
+                "core.weechat",
+                "irc.libera.alice2",
+                "irc.libera.#rust",
+                "irc.libera.#weechat",
+            ];
+            run_until(&mut wee, |w| buffers(w) == expected(&names)).await;
+            assert_eq!(
+                "irc.libera.alice2",
+                wee.get_current_buffer().unwrap().full_name
+            );
+
+            relay.close_buffer("irc.libera.#rust");
+            let names = ["core.weechat", "irc.libera.alice2", "irc.libera.#weechat"];
+            run_until(&mut wee, |w| buffers(w) == expected(&names)).await;
+            wee.close().await.unwrap();
+        });
+    }
+
+    #[test]
+    fn test_upgrade() {
+        smol::block_on(async {
+            let relay = FakeRelay::start(Settings::default()).await;
+            let mut wee = Wee::connect(&relay.conf("secret", None), no_prompt)
+                .await
+                .unwrap();
+            wee.sync().await.unwrap();
+            assert_eq!("sync *", relay.expect_command("sync").await);
+            wee.switch_current_buffer(&String::from("irc.libera.#rust"))
+                .await
+                .unwrap();
+            run_until(&mut wee, |w| w.get_lines().len() == 2).await;
+            let old_ptr = wee.get_current_buffer().unwrap().ptr_buffer;
+
+            relay.upgrade();
+            run_until(&mut wee, |w| w.is_upgrading()).await;
+            // all buffers are synced at once, rather than by name.
+            assert_eq!(
+                "desync * buffers,buffer,nicklist",
+                relay.expect_command("desync ").await
+            );
+            run_until(&mut wee, |w| !w.is_upgrading()).await;
+            assert_eq!("sync *", relay.expect_command("sync").await);
+            let current = wee.get_current_buffer().unwrap();
+            assert_eq!("irc.libera.#rust", current.full_name);
+            assert_ne!(old_ptr, current.ptr_buffer);
+
+            // lines are refetched, and new ones received.
+            run_until(&mut wee, |w| w.get_lines().len() == 2).await;
+            relay.push_line("irc.libera.#rust", "alice", "upgraded");
+            run_until(&mut wee, |w| w.get_lines().len() == 3).await;
+            assert_eq!(Some(&"upgraded"), messages(&wee).last());
+            wee.close().await.unwrap();
+        });
+    }
+
+    #[test]
+    fn test_ping() {
+        smol::block_on(async {
+            let relay = FakeRelay::start(Settings::default()).await;
+            let mut wee = Wee::connect(&relay.conf("secret", None), no_prompt)
+                .await
+                .unwrap();
+            assert!(wee.pinger.is_some());
+            let interval = Duration::from_millis(20);
+            wee.pinger = Some(super::super::ping::Pinger::new(interval, TIMEOUT));
+            wee.sync().await.unwrap();
+            // pings follow each other once answered.
+            let second_ping = relay.expect_command("ping weesels2");
+            let mut second_ping = Box::pin(second_ping.fuse());
+            loop {
+                select! {
+                    _ = second_ping => break,
+                    res = wee.run().fuse() => res.unwrap(),
+                }
+            }
+            assert!(wee.lag().unwrap() < TIMEOUT);
+            wee.close().await.unwrap();
+        });
+    }
+
+    #[test]
+    fn test_sync_profiles() {
+        smol::block_on(async {
+            let relay = FakeRelay::start(Settings::default()).await;
+            let mut conf = relay.conf("secret", None);
+            conf.sync
+                .insert(String::from("irc.*"), crate::wee::SyncProfile::Buffer);
+            let mut wee = Wee::connect(&conf, no_prompt).await.unwrap();
+            wee.sync().await.unwrap();
+            relay.expect_command("sync * buffers,upgrade").await;
+            assert_eq!("sync core.weechat", relay.expect_command("sync ").await);
+            run_until(&mut wee, |w| w.get_buffers()[1].hotlist.1 == 2).await;
+
+            // lines of the buffer are not received.
+            relay.push_line("irc.libera.#rust", "alice", "unseen");
+            relay.push_line("core.weechat", "--", "seen");
+            run_until(&mut wee, |w| w.get_lines().len() == 3).await;
+            assert_eq!(2, wee.get_buffers()[1].hotlist.1);
+            // their hotlist is fetched periodically instead.
+            wee.next_hotlist = std::time::Instant::now();
+            run_until(&mut wee, |w| w.get_buffers()[1].hotlist.1 == 3).await;
+
+            // unless it is shown.
+            let rust = String::from("irc.libera.#rust");
+            wee.switch_current_buffer(&rust).await.unwrap();
+            relay.expect_command("sync irc.libera.#rust").await;
+            run_until(&mut wee, |w| w.get_lines().len() == 3).await;
+            relay.push_line("irc.libera.#rust", "alice", "seen");
+            run_until(&mut wee, |w| w.get_lines().len() == 4).await;
+            wee.switch_current_buffer(&String::from("core.weechat"))
+                .await
+                .unwrap();
+            relay.expect_command("desync irc.libera.#rust").await;
+
+            // lines missed while desynced are fetched when shown again.
+            relay.push_line("irc.libera.#rust", "alice", "missed");
+            wee.switch_current_buffer(&rust).await.unwrap();
+            relay.expect_command("sync irc.libera.#rust").await;
+            run_until(&mut wee, |w| messages(w).last() == Some(&"missed")).await;
+            wee.switch_current_buffer(&String::from("core.weechat"))
+                .await
+                .unwrap();
+            relay.expect_command("desync irc.libera.#rust").await;
+
+            wee.set_sync_profile(&rust, crate::wee::SyncProfile::Full)
+                .await
+                .unwrap();
+            relay.expect_command("sync irc.libera.#rust").await;
+            wee.close().await.unwrap();
+        });
+    }
+
+    #[test]
+    fn test_sync_all() {
+        smol::block_on(async {
+            let relay = FakeRelay::start(Settings::default()).await;
+            let mut wee = Wee::connect(&relay.conf("secret", None), no_prompt)
+                .await
+                .unwrap();
+            wee.sync().await.unwrap();
+            assert_eq!("sync *", relay.expect_command("sync").await);
+
+            // other buffers are synced by name, once one is not synced.
+            let rust = String::from("irc.libera.#rust");
+            wee.set_sync_profile(&rust, crate::wee::SyncProfile::None)
+                .await
+                .unwrap();
+            assert_eq!("sync core.weechat", relay.expect_command("sync").await);
+            relay.expect_command("desync * buffer,nicklist").await;
+            relay.expect_command("desync irc.libera.#rust").await;
+
+            wee.set_sync_profile(&rust, crate::wee::SyncProfile::Full)
+                .await
+                .unwrap();
+            assert_eq!("sync *", relay.expect_command("sync").await);
+            wee.close().await.unwrap();
+        });
+    }
+
+    #[test]
+    fn test_record_replay() {
+        smol::block_on(async {
+            let dir = tempfile::TempDir::new().unwrap();
+            let path = dir.path().join("session.rec");
+            let relay = FakeRelay::start(Settings::default()).await;
+            let mut wee = Wee::connect(&relay.conf("secret", None), no_prompt)
+                .await
+                .unwrap();
+            wee.record(&path).unwrap();
+            wee.sync().await.unwrap();
+            assert_eq!("sync *", relay.expect_command("sync").await);
+            run_until(&mut wee, |w| w.get_lines().len() == 2).await;
+            relay.push_line("core.weechat", "alice", "recorded");
+            run_until(&mut wee, |w| w.get_lines().len() == 3).await;
+            wee.close().await.unwrap();
+            drop(relay);
+
+            // the session is replayed without the relay.
+            let mut wee = Wee::replay(&path).unwrap();
+            wee.sync().await.unwrap();
+            assert_eq!(2, wee.get_buffers().len());
+            run_until(&mut wee, |w| w.get_lines().len() == 3).await;
+            assert_eq!(
+                vec!["weechat backlog 0", "weechat backlog 1", "recorded"],
+                messages(&wee)
+            );
+            wee.close().await.unwrap();
+        });
+    }
+
+    #[test]
+    fn test_unix_socket() {
+        smol::block_on(async {
+            let dir = tempfile::TempDir::new().unwrap();
+            let relay = FakeRelay::start_unix(Settings::default(), &dir.path().join("relay")).await;
+            let mut wee = Wee::connect(&relay.conf("secret", None), no_prompt)
+                .await
+                .unwrap();
+            wee.sync().await.unwrap();
+            assert_eq!(2, wee.get_buffers().len());
+            wee.close().await.unwrap();
+        });
+    }
+}
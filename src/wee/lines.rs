@@ -0,0 +1,182 @@
+use super::messages::LineData;
+
+/// Maximum number of lines kept per buffer.
+const MAX_LINES: usize = 500;
+
+/// Lines of a buffer, oldest first, kept up to date from line events.
+#[derive(Debug)]
+pub struct BufferLines {
+    lines: Vec<LineData>,
+    /// Index before which lines may be missing: lines were never fetched, or
+    /// were missed while disconnected. None when there is no gap.
+    gap: Option<usize>,
+}
+
+impl Default for BufferLines {
+    fn default() -> Self {
+        BufferLines {
+            lines: vec![],
+            gap: Some(0),
+        }
+    }
+}
+
+impl BufferLines {
+    /// Lines of a buffer that was just cleared, with nothing to fetch.
+    pub fn cleared() -> Self {
+        BufferLines {
+            lines: vec![],
+            gap: None,
+        }
+    }
+
+    pub fn lines(&self) -> &[LineData] {
+        &self.lines
+    }
+
+    /// Whether older lines must be fetched to fill a gap.
+    pub fn needs_backfill(&self) -> bool {
+        self.gap.is_some()
+    }
+
+    /// Append a new line.
+    pub fn push(&mut self, line: LineData) {
+        self.lines.push(line);
+        if self.lines.len() > MAX_LINES {
+            let extra = self.lines.len() - MAX_LINES;
+            self.lines.drain(..extra);
+            self.gap = match self.gap {
+                // the lines before the gap are all gone, the rest is contiguous.
+                Some(gap) if gap <= extra => None,
+                gap => gap.map(|g| g - extra),
+            };
+        }
+    }
+
+    /// Mark that new lines may be missed, after a disconnection or desync.
+    pub fn disconnected(&mut self) {
+        if let Some(gap) = self.gap {
+            // only a single gap is tracked, drop lines before the older one.
+            self.lines.drain(..gap);
+        }
+        self.gap = Some(self.lines.len());
+    }
+
+    /// Fill the gap with the last lines of the buffer, oldest first, as
+    /// fetched from the relay.
+    pub fn backfill(&mut self, last_lines: Vec<LineData>) {
+        let gap = match self.gap.take() {
+            Some(gap) => gap,
+            None => return,
+        };
+        let fresh = self.lines.split_off(gap);
+        let stale = std::mem::take(&mut self.lines);
+        let mut older: Vec<LineData> = last_lines
+            .into_iter()
+            .take_while(|l| !matches!(fresh.first(), Some(f) if same(f, l)))
+            .filter(|l| !fresh.iter().any(|f| same(f, l)))
+            .collect();
+        // keep the lines from before the gap only when it is closed.
+        if let Some(last_stale) = stale.last() {
+            if let Some(pos) = older.iter().position(|l| same(l, last_stale)) {
+                older.drain(..=pos);
+                self.lines = stale;
+            }
+        }
+        self.lines.extend(older);
+        for line in fresh {
+            self.push(line);
+        }
+    }
+}
+
+/// Whether two lines are the same weechat line.
+fn same(a: &LineData, b: &LineData) -> bool {
+    a.ptr_line_data.is_some() && a.ptr_line_data == b.ptr_line_data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::messages::Pointer;
+    use super::*;
+
+    fn line(ptr: u64) -> LineData {
+        LineData {
+            ptr_line: None,
+            ptr_line_data: Some(Pointer(ptr)),
+            buffer: Pointer(0x1234),
+            date: time::OffsetDateTime::unix_epoch(),
+            displayed: 1,
+            highlight: 0,
+            prefix: None,
+            message: format!("line {}", ptr),
+            notify_level: 1,
+            tags_array: vec![],
+        }
+    }
+
+    fn lines(range: std::ops::Range<u64>) -> Vec<LineData> {
+        range.map(line).collect()
+    }
+
+    fn ptrs(lines: &BufferLines) -> Vec<u64> {
+        lines
+            .lines()
+            .iter()
+            .map(|l| l.ptr_line_data.unwrap().0)
+            .collect()
+    }
+
+    #[test]
+    fn test_backfill_new_buffer() {
+        let mut buf = BufferLines::default();
+        assert!(buf.needs_backfill());
+        // lines received before the backfill response, or included in it.
+        buf.push(line(5));
+        buf.push(line(6));
+        buf.backfill(lines(1..6));
+        assert_eq!(vec![1, 2, 3, 4, 5, 6], ptrs(&buf));
+        assert!(!buf.needs_backfill());
+    }
+
+    #[test]
+    fn test_backfill_covered() {
+        let mut buf = BufferLines::default();
+        for l in lines(1..10) {
+            buf.push(l);
+        }
+        buf.backfill(lines(5..10));
+        assert_eq!((1..10).collect::<Vec<_>>(), ptrs(&buf));
+    }
+
+    #[test]
+    fn test_backfill_after_disconnect() {
+        let mut buf = BufferLines::default();
+        buf.backfill(lines(1..4));
+        buf.disconnected();
+        assert!(buf.needs_backfill());
+        buf.push(line(7));
+        // missed lines 4 to 6
+        buf.backfill(lines(2..8));
+        assert_eq!((1..8).collect::<Vec<_>>(), ptrs(&buf));
+
+        // too many missed lines, the stale ones are dropped.
+        buf.disconnected();
+        buf.backfill(lines(10..12));
+        assert_eq!(vec![10, 11], ptrs(&buf));
+    }
+
+    #[test]
+    fn test_bounded() {
+        let mut buf = BufferLines::default();
+        buf.backfill(lines(0..10));
+        buf.disconnected();
+        for l in lines(10..MAX_LINES as u64 + 20) {
+            buf.push(l);
+        }
+        assert_eq!(MAX_LINES, buf.lines().len());
+        assert_eq!(Some(&20), ptrs(&buf).first());
+        // the stale lines are all dropped.
+        assert!(!buf.needs_backfill());
+    }
+}
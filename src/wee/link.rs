@@ -0,0 +1,245 @@
+use super::record::{Direction, Recorder};
+use super::{compression, Error, Result, Stream};
+use async_channel::{Receiver, Sender};
+use log::trace;
+use smol::io::{AsyncReadExt, AsyncWriteExt};
+use smol::Task;
+use std::convert::TryInto;
+use std::time::Duration;
+
+const READ_CHUNK_SIZE: usize = 4096;
+/// Largest frame accepted from the relay, compressed or not.
+pub const MAX_FRAME_SIZE: usize = 32 * 1024 * 1024;
+/// Time given to the writer to send pending commands when closing.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Incremental frame decoder, keeping partial frames between reads.
+#[derive(Default)]
+pub struct FrameReader {
+    buf: Vec<u8>,
+}
+
+impl FrameReader {
+    /// Append bytes received from the relay.
+    pub fn feed(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Pop the next complete frame, as received.
+    pub fn next_frame(&mut self) -> Result<Option<Vec<u8>>> {
+        if self.buf.len() < 5 {
+            return Ok(None);
+        }
+        let len = u32::from_be_bytes(self.buf[..4].try_into().unwrap()) as usize;
+        if len < 5 {
+            return Err(Error::ProtocolError("invalid message length"));
+        }
+        if len > MAX_FRAME_SIZE {
+            return Err(Error::ProtocolError("message too long"));
+        }
+        if self.buf.len() < len {
+            return Ok(None);
+        }
+        Ok(Some(self.buf.drain(..len).collect()))
+    }
+}
+
+/// Decompress a raw frame, and strip its header.
+fn decode_frame(mut raw: Vec<u8>) -> Result<Vec<u8>> {
+    let flag = raw[4];
+    compression::decompress(flag, raw.split_off(5))
+}
+
+/// Background reader and writer tasks of an authenticated connection.
+///
+/// Dropping the link cancels both tasks.
+pub struct Link {
+    incoming: Receiver<Result<Vec<u8>>>,
+    reader: Task<()>,
+    writer: Task<()>,
+}
+
+impl Link {
+    /// Start exchanging frames over `stream`, sending the commands queued
+    /// in `outgoing`. An empty command closes the session.
+    pub fn start(stream: Box<dyn Stream>, outgoing: Receiver<String>, recorder: Recorder) -> Self {
+        let (read_half, write_half) = futures::AsyncReadExt::split(stream);
+        let (sender, incoming) = async_channel::unbounded();
+        let reader = smol::spawn(read_frames(read_half, sender.clone(), recorder.clone()));
+        let writer = smol::spawn(write_commands(write_half, outgoing, sender, recorder));
+        Link {
+            incoming,
+            reader,
+            writer,
+        }
+    }
+
+    /// Receive the next frame. This is cancellation-safe.
+    pub async fn recv(&self) -> Result<Vec<u8>> {
+        self.incoming
+            .recv()
+            .await
+            .unwrap_or(Err(Error::ProtocolError("connection closed")))
+    }
+
+    /// Wait until the writer has sent the quit command, and stop reading.
+    pub async fn close(self) {
+        let writer = self.writer;
+        smol::future::or(writer, async {
+            smol::Timer::after(CLOSE_TIMEOUT).await;
+            log::error!("Timed out sending quit command");
+        })
+        .await;
+        self.reader.cancel().await;
+    }
+}
+
+async fn read_frames<R>(mut stream: R, frames: Sender<Result<Vec<u8>>>, recorder: Recorder)
+where
+    R: AsyncReadExt + Unpin,
+{
+    let mut reader = FrameReader::default();
+    let mut chunk = vec![0u8; READ_CHUNK_SIZE];
+    loop {
+        let len = match stream.read(&mut chunk).await {
+            Ok(0) => {
+                let eof = std::io::Error::from(std::io::ErrorKind::UnexpectedEof);
+                let _ = frames.send(Err(eof.into())).await;
+                return;
+            }
+            Ok(len) => len,
+            Err(e) => {
+                let _ = frames.send(Err(e.into())).await;
+                return;
+            }
+        };
+        reader.feed(&chunk[..len]);
+        loop {
+            let frame = reader.next_frame().and_then(|raw| match raw {
+                Some(raw) => {
+                    recorder.record(Direction::Received, &raw);
+                    decode_frame(raw).map(Some)
+                }
+                None => Ok(None),
+            });
+            match frame {
+                Ok(None) => break,
+                Ok(Some(frame)) => {
+                    if frames.send(Ok(frame)).await.is_err() {
+                        return;
+                    }
+                }
+                Err(e) => {
+                    // the stream can't be resynchronized after this.
+                    let _ = frames.send(Err(e)).await;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+async fn write_commands<W>(
+    mut stream: W,
+    outgoing: Receiver<String>,
+    errors: Sender<Result<Vec<u8>>>,
+    recorder: Recorder,
+) where
+    W: AsyncWriteExt + Unpin,
+{
+    while let Ok(command) = outgoing.recv().await {
+        let quit = command.is_empty();
+        let command = if quit {
+            String::from("(quit) quit\n")
+        } else {
+            command
+        };
+        trace!("sending {:?}", command);
+        recorder.record(Direction::Sent, command.as_bytes());
+        let res = stream.write_all(command.as_bytes()).await;
+        let res = match res {
+            Ok(()) => stream.flush().await,
+            err => err,
+        };
+        if let Err(e) = res {
+            let _ = errors.send(Err(e.into())).await;
+            return;
+        }
+        if quit {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: &[u8] = b"\x00\x00\x00\x2b\x00\x00\x00\x00\rversion_checkinf\x00\x00\x00\
+        \x07version\x00\x00\x00\x032.9";
+
+    #[test]
+    fn test_partial_frames() {
+        let mut reader = FrameReader::default();
+        let mut data = FRAME.to_vec();
+        data.extend_from_slice(FRAME);
+        for b in data[..FRAME.len() - 1].iter() {
+            assert!(reader.next_frame().unwrap().is_none());
+            reader.feed(&[*b]);
+        }
+        assert!(reader.next_frame().unwrap().is_none());
+        reader.feed(&data[FRAME.len() - 1..]);
+        for _ in 0..2 {
+            let frame = reader.next_frame().unwrap().expect("complete frame");
+            assert_eq!(FRAME, &frame[..]);
+            assert_eq!(&FRAME[5..], &decode_frame(frame).unwrap()[..]);
+        }
+        assert!(reader.next_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_invalid_length() {
+        let mut reader = FrameReader::default();
+        reader.feed(b"\x00\x00\x00\x02\x00");
+        assert!(reader.next_frame().is_err());
+    }
+
+    #[test]
+    fn test_too_long() {
+        let mut reader = FrameReader::default();
+        reader.feed(b"\xff\xff\xff\xff\x00");
+        assert!(matches!(
+            reader.next_frame(),
+            Err(Error::ProtocolError("message too long"))
+        ));
+    }
+
+    #[test]
+    fn test_link() {
+        smol::block_on(async {
+            let (client, mut server) = smol::net::unix::UnixStream::pair().unwrap();
+            let (queue, outgoing) = async_channel::unbounded();
+            let link = Link::start(Box::new(client), outgoing, Recorder::default());
+
+            queue
+                .send(String::from("(id) info version\n"))
+                .await
+                .unwrap();
+            let mut received = vec![0u8; 18];
+            server.read_exact(&mut received).await.unwrap();
+            assert_eq!(b"(id) info version\n", &received[..]);
+
+            // frames split across writes are reassembled.
+            server.write_all(&FRAME[..10]).await.unwrap();
+            server.flush().await.unwrap();
+            server.write_all(&FRAME[10..]).await.unwrap();
+            assert_eq!(&FRAME[5..], &link.recv().await.unwrap()[..]);
+
+            queue.send(String::new()).await.unwrap();
+            link.close().await;
+            let mut received = String::new();
+            server.read_to_string(&mut received).await.unwrap();
+            assert_eq!("(quit) quit\n", received);
+        });
+    }
+}
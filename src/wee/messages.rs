@@ -1,4 +1,56 @@
+use serde::de::{self, Deserializer, Visitor};
 use serde::Deserialize;
+use std::collections::HashMap;
+use time::OffsetDateTime;
+
+/// Address of a weechat object, formatted as "0x" and hex digits.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Pointer(pub u64);
+
+impl std::fmt::Display for Pointer {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "0x{:x}", self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Pointer {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct PointerVisitor;
+
+        impl<'de> Visitor<'de> for PointerVisitor {
+            type Value = Pointer;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a hex pointer")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Pointer, E> {
+                u64::from_str_radix(v.trim_start_matches("0x"), 16)
+                    .map(Pointer)
+                    .map_err(|_| E::invalid_value(de::Unexpected::Str(v), &self))
+            }
+        }
+
+        deserializer.deserialize_str(PointerVisitor)
+    }
+}
+
+/// Timestamps of the years supported by `time`, from -9999 to 9999.
+const TIMESTAMPS: std::ops::RangeInclusive<i64> = -377_705_116_800..=253_402_300_799;
+
+/// Decode a tim value, in seconds since the epoch.
+pub(crate) fn timestamp<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<OffsetDateTime, D::Error> {
+    let timestamp = i64::deserialize(deserializer)?;
+    if !TIMESTAMPS.contains(&timestamp) {
+        return Err(de::Error::custom(format!(
+            "timestamp {} out of range",
+            timestamp
+        )));
+    }
+    Ok(OffsetDateTime::from_unix_timestamp(timestamp))
+}
 
 #[derive(Deserialize, Debug)]
 pub struct Handshake {
@@ -21,18 +73,49 @@ pub struct Info {
     pub inf: (String, Option<String>),
 }
 
+/// Answer to a ping, with its argument.
+#[derive(Deserialize, Debug)]
+pub struct Pong {
+    pub id: String,
+    pub str: Option<String>,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct Buffer {
-    pub ptr_buffer: String,
+    pub ptr_buffer: Pointer,
     pub number: i32,
     pub short_name: Option<String>,
     pub full_name: String,
     pub title: Option<String>,
 
+    #[serde(default = "default_display_groups")]
+    pub nicklist_display_groups: i32,
+    /// 0 formatted, 1 free content.
+    #[serde(rename = "type", default)]
+    pub buffer_type: i32,
+    #[serde(default)]
+    pub hidden: i32,
+    #[serde(default)]
+    pub local_variables: HashMap<String, String>,
+
     #[serde(skip, default = "default_hotlist")]
     pub hotlist: (i32, i32, i32, i32),
 }
 
+/// Buffer fields sent with `_buffer_*` events. Fields other than the
+/// number and names are only set by the events changing them.
+#[derive(Deserialize, Debug)]
+pub struct BufferEvent {
+    pub ptr_buffer: Pointer,
+    pub number: i32,
+    pub full_name: String,
+    pub short_name: Option<String>,
+    pub title: Option<String>,
+    #[serde(rename = "type")]
+    pub buffer_type: Option<i32>,
+    pub local_variables: Option<HashMap<String, String>>,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct BuffersResponse {
     pub id: String,
@@ -42,7 +125,7 @@ pub struct BuffersResponse {
 #[derive(Deserialize, Debug)]
 pub struct Hotlist {
     pub priority: i32,
-    pub buffer: String,
+    pub buffer: Pointer,
     pub count: (i32, i32, i32, i32), // counts per urgency least -> most
 }
 
@@ -59,15 +142,20 @@ pub struct LineAddr {
 
 #[derive(Deserialize, Debug)]
 pub struct LineData {
-    pub ptr_line: Option<String>,
-    pub buffer: String,
-    pub date: String,
+    pub ptr_line: Option<Pointer>,
+    pub ptr_line_data: Option<Pointer>,
+    pub buffer: Pointer,
+    #[serde(deserialize_with = "timestamp")]
+    pub date: OffsetDateTime,
     pub displayed: u8,
     pub highlight: u8,
     pub prefix: Option<String>,
     pub message: String,
     /// -1 disabled, 0 low, 1 message, 2 private, 3 highlight
     pub notify_level: i8,
+    /// e.g. "irc_join", "nick_alice", "irc_smart_filter"
+    #[serde(default)]
+    pub tags_array: Vec<String>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -80,6 +168,26 @@ fn default_hotlist() -> (i32, i32, i32, i32) {
     (0, 0, 0, 0)
 }
 
+fn default_display_groups() -> i32 {
+    1
+}
+
+/// A nicklist group or nick, from nicklist hdata and diff events.
+#[derive(Deserialize, Debug, Clone)]
+pub struct NicklistItem {
+    pub ptr_buffer: Pointer,
+    /// Only set in diffs: '^' parent group, '+' added, '-' removed, '*' updated.
+    #[serde(rename = "_diff", default)]
+    pub diff: i8,
+    pub group: i8,
+    pub visible: i8,
+    pub level: i32,
+    pub name: String,
+    pub color: Option<String>,
+    pub prefix: Option<String>,
+    pub prefix_color: Option<String>,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct CompletionData {
     pub context: String,
@@ -1,36 +1,113 @@
 use async_channel::{Receiver, Sender};
-use futures::{future::FutureExt, select, AsyncRead, AsyncWrite};
+use futures::future::{FutureExt, LocalBoxFuture};
+use futures::stream::{FuturesUnordered, StreamExt};
+use futures::{select, AsyncRead, AsyncWrite, Future};
+use link::Link;
 use log::{info, trace};
 use smol::{io::AsyncReadExt, io::AsyncWriteExt, Async};
 use std::borrow::Borrow;
-use std::cell::RefCell;
-use std::net::TcpStream;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::time::{Duration, Instant};
 
-pub use messages::{Buffer, CompletionData, LineData};
+use lines::BufferLines;
+use messages::BufferEvent;
+pub use messages::{Buffer, CompletionData, LineData, NicklistItem, Pointer};
+pub use nicklist::{group_name, Nicklist};
+use ping::Pinger;
+use record::Recorder;
+pub use sync::SyncProfile;
+use sync::SyncProfiles;
 
+/// Number of lines fetched when filling a buffer or scrolling back.
 const BUFFER_CACHE_SIZE: usize = 100;
+/// Interval between hotlist fetches, while some buffers are not synced.
+const HOTLIST_INTERVAL: Duration = Duration::from_secs(30);
 
 pub mod auth;
+mod compression;
 mod de;
+#[cfg(test)]
+mod fake_relay;
+mod lines;
+mod link;
 mod messages;
+mod nicklist;
+mod ping;
+mod proxy;
+mod record;
+mod sync;
+// only used to build relay messages in tests, for now.
+#[cfg(test)]
+mod ser;
+mod tls;
+mod totp;
+mod websocket;
 
 #[derive(Debug)]
 pub enum Error {
-    PacketError { source: de::Error },
+    PacketError {
+        source: de::Error,
+    },
     ProtocolError(&'static str),
-    IOError { source: std::io::Error },
-    TlsError { source: async_native_tls::Error },
-    OpensslError { source: openssl::error::ErrorStack },
+    IOError {
+        source: std::io::Error,
+    },
+    TlsError {
+        source: async_native_tls::Error,
+    },
+    OpensslError {
+        source: openssl::error::ErrorStack,
+    },
+    ConfigError(&'static str),
+    WebSocketError(String),
+    /// The relay certificate was rejected, or the TLS options are invalid.
+    CertificateError(String),
+    ProxyError(String),
+    /// The relay asks for a TOTP code, which cannot be prompted for.
+    TotpRequired,
 }
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
-        f.write_fmt(format_args!("{:?}", self))
+        match self {
+            Error::TlsError { source } => write!(f, "TLS error: {}", source),
+            Error::CertificateError(msg) => write!(f, "TLS error: {}", msg),
+            Error::ConfigError(msg) => write!(f, "Invalid configuration: {}", msg),
+            Error::ProxyError(msg) => write!(f, "Proxy error: {}", msg),
+            Error::TotpRequired => f.write_str("TOTP code needed, restart to enter it"),
+            _ => f.write_fmt(format_args!("{:?}", self)),
+        }
     }
 }
 
 impl std::error::Error for Error {}
 
+impl Error {
+    /// Whether the error means the connection is lost, and a reconnection
+    /// may recover from it.
+    pub fn is_disconnect(&self) -> bool {
+        match self {
+            Error::IOError { .. } | Error::TlsError { .. } => true,
+            _ => self.is_eof(),
+        }
+    }
+
+    /// Whether the error is caused by the connection being closed.
+    fn is_eof(&self) -> bool {
+        match self {
+            Error::PacketError {
+                source: de::Error::Eof,
+            } => true,
+            Error::IOError { source } => source.kind() == std::io::ErrorKind::UnexpectedEof,
+            _ => false,
+        }
+    }
+}
+
 impl std::convert::From<std::io::Error> for Error {
     fn from(source: std::io::Error) -> Self {
         Error::IOError { source }
@@ -55,82 +132,392 @@ impl std::convert::From<openssl::error::ErrorStack> for Error {
     }
 }
 
-trait Stream: AsyncRead + AsyncWrite + Unpin {}
-impl<T: AsyncRead + AsyncWrite + Unpin> Stream for T {}
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
 type Result<T> = std::result::Result<T, Error>;
 
+/// Callback asking the user for a TOTP code, when no secret is configured.
+pub type TotpPrompt = fn() -> std::io::Result<String>;
+
+/// Response to a request sent in the background, with the buffer it is
+/// for.
+enum Response {
+    Nicklist(Pointer, Vec<NicklistItem>),
+    /// Last lines of a buffer, newest first.
+    Backlog(Pointer, Vec<LineData>),
+    /// Lines before the ones shown, newest first.
+    Scrollback(Pointer, Vec<LineData>),
+    /// Buffer list after an upgrade.
+    Buffers(Vec<Buffer>),
+}
+
 /// Weechat relay client.
 pub struct Wee {
-    stream: Box<dyn Stream>,
+    link: Link,
     current_buffer: RefCell<String>,
     bufs: Vec<Buffer>,
-    buf_lines: Vec<messages::LineData>,
+    /// Lines of each buffer, by buffer pointer.
+    buf_lines: HashMap<Pointer, BufferLines>,
+    /// Lines shown while scrolling back, instead of the current buffer lines.
+    scrollback_lines: Vec<LineData>,
+    nicklist: Nicklist,
     send_queue: (Sender<String>, Receiver<String>),
-    completion: RefCell<Option<messages::CompletionData>>,
+    /// Requests waiting for a response, by generated id.
+    pending: RefCell<HashMap<String, Sender<Vec<u8>>>>,
+    next_request: Cell<u64>,
+    /// Responses awaited in the background, handled by `run()`.
+    responses: FuturesUnordered<LocalBoxFuture<'static, Result<Response>>>,
+    recorder: Recorder,
+    /// Whether weechat is running /upgrade, until resynced.
+    upgrading: bool,
+    pinger: Option<Pinger>,
+    /// Time of the next hotlist fetch, for buffers whose lines are not
+    /// received.
+    next_hotlist: Instant,
+    sync_profiles: SyncProfiles,
     pub is_scrolling: bool,
 }
 
 impl Wee {
-    pub async fn connect(conf: &crate::config::Conf) -> Result<Wee> {
-        let stream = connect(conf.host.as_str(), conf.port, conf.ssl, conf.insecure)
-            .await
-            .unwrap();
+    pub async fn connect(conf: &crate::config::Conf, totp_prompt: TotpPrompt) -> Result<Wee> {
+        let mut stream = connect(conf).await?;
+        auth(&mut stream, conf, Some(totp_prompt)).await?;
+        let mut wee = Wee::new(stream);
+        wee.pinger = Pinger::from_conf(conf);
+        wee.sync_profiles = SyncProfiles::new(conf.sync.clone());
+        Ok(wee)
+    }
+
+    /// Create a client replaying the frames received in a recording, instead
+    /// of connecting to a relay. Sent commands are discarded.
+    pub fn replay(path: &Path) -> Result<Wee> {
+        Ok(Wee::new(record::replay(record::load(path)?)?))
+    }
+
+    /// Create a client over an authenticated stream.
+    fn new(stream: Box<dyn Stream>) -> Wee {
+        let send_queue = async_channel::unbounded();
         let current_buffer = RefCell::new(String::from(""));
-        let mut wee = Wee {
-            stream,
+        let recorder = Recorder::default();
+        Wee {
+            link: Link::start(stream, send_queue.1.clone(), recorder.clone()),
             current_buffer,
             bufs: vec![],
-            buf_lines: vec![],
-            send_queue: async_channel::unbounded(),
+            buf_lines: HashMap::new(),
+            scrollback_lines: vec![],
+            nicklist: Nicklist::default(),
+            send_queue,
+            pending: RefCell::new(HashMap::new()),
+            next_request: Cell::new(0),
+            responses: FuturesUnordered::new(),
+            recorder,
+            upgrading: false,
+            pinger: None,
+            next_hotlist: Instant::now() + HOTLIST_INTERVAL,
+            sync_profiles: SyncProfiles::default(),
             is_scrolling: false,
-            completion: RefCell::new(None),
+        }
+    }
+
+    /// Record the frames exchanged from now on to `path`, including after
+    /// reconnections.
+    pub fn record(&self, path: &Path) -> Result<()> {
+        self.recorder.start(path)
+    }
+
+    /// Replace a lost connection, keeping the client state.
+    /// Call `sync()` afterwards to refresh it.
+    ///
+    /// TOTP codes are not prompted for, as the user is busy elsewhere: this
+    /// fails with `Error::TotpRequired` unless a TOTP secret is configured.
+    pub async fn reconnect(&mut self, conf: &crate::config::Conf) -> Result<()> {
+        let mut stream = connect(conf).await?;
+        auth(&mut stream, conf, None).await?;
+        // drop requests queued for the previous connection, cancelling
+        // their response.
+        while self.send_queue.1.try_recv().is_ok() {}
+        self.pending.borrow_mut().clear();
+        self.responses = FuturesUnordered::new();
+        self.link = Link::start(stream, self.send_queue.1.clone(), self.recorder.clone());
+        self.upgrading = false;
+        self.pinger = Pinger::from_conf(conf);
+        self.is_scrolling = false;
+        for lines in self.buf_lines.values_mut() {
+            lines.disconnected();
+        }
+        Ok(())
+    }
+
+    /// Fetch the buffer list, restore the current buffer (defaulting to the
+    /// core buffer) and subscribe to events.
+    pub async fn sync(&mut self) -> Result<()> {
+        let bufs: messages::BuffersResponse = self.call("hdata buffer:gui_buffers(*)").await?;
+        self.set_buffers(bufs.hda);
+        let current = match self.current_buffer.borrow().as_str() {
+            "" => String::from("core.weechat"),
+            name => String::from(name),
         };
-        wee.auth(conf.password.as_str()).await?;
-        Ok(wee)
+        self.switch_current_buffer(&current).await?; // request buffer data
+        self.hotlist().await?;
+        self.subscribe().await
+    }
+
+    /// Subscribe to buffer list changes, and to the lines and nicklists of
+    /// synced buffers.
+    async fn subscribe(&self) -> Result<()> {
+        if self.sync_profiles.all_full() {
+            return self.send("sync", "sync *").await;
+        }
+        self.send("sync", "sync * buffers,upgrade").await?;
+        match self.synced_names() {
+            Some(names) => self.send("sync", &format!("sync {}", names)).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Comma separated names of the buffers synced by name, if any. All
+    /// buffers are synced at once when none has another profile.
+    fn synced_names(&self) -> Option<String> {
+        if self.sync_profiles.all_full() {
+            return None;
+        }
+        let names: Vec<&str> = self
+            .bufs
+            .iter()
+            .map(|b| b.full_name.as_str())
+            .filter(|name| self.is_synced(name))
+            .collect();
+        if names.is_empty() {
+            None
+        } else {
+            Some(names.join(","))
+        }
+    }
+
+    /// Whether lines of a buffer are received: it is synced fully, or shown.
+    fn is_synced(&self, full_name: &str) -> bool {
+        self.sync_profiles.get(full_name) == SyncProfile::Full
+            || *self.current_buffer.borrow() == full_name
+    }
+
+    /// Change the sync profile of a buffer, until the client exits.
+    pub async fn set_sync_profile(&mut self, full_name: &str, profile: SyncProfile) -> Result<()> {
+        let was_synced = self.is_synced(full_name);
+        let was_all_full = self.sync_profiles.all_full();
+        self.sync_profiles.set(full_name, profile);
+        match (was_all_full, self.sync_profiles.all_full()) {
+            (false, true) => return self.send("sync", "sync *").await,
+            (true, false) => {
+                // buffers synced with "*" can't be desynced by name, so sync
+                // the others by name first.
+                if let Some(names) = self.synced_names() {
+                    self.send("sync", &format!("sync {}", names)).await?;
+                }
+                self.send("", "desync * buffer,nicklist").await?;
+                return if self.is_synced(full_name) {
+                    Ok(())
+                } else {
+                    self.desync(full_name).await
+                };
+            }
+            _ => (),
+        }
+        match (was_synced, self.is_synced(full_name)) {
+            (false, true) => self.send("sync", &format!("sync {}", full_name)).await,
+            (true, false) => self.desync(full_name).await,
+            _ => Ok(()),
+        }
+    }
+
+    /// Stop receiving lines of a buffer, which cached lines will then miss.
+    async fn desync(&mut self, full_name: &str) -> Result<()> {
+        self.send("", &format!("desync {}", full_name)).await?;
+        if let Some(buffer) = self.bufs.iter().find(|b| b.full_name == full_name) {
+            if let Some(lines) = self.buf_lines.get_mut(&buffer.ptr_buffer) {
+                lines.disconnected();
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace the buffer list after an upgrade, which changes all pointers,
+    /// keeping the current buffer. Scrolling back stops, as the lines
+    /// scrolled back to can't be fetched further.
+    async fn resync(&mut self, bufs: Vec<Buffer>) -> Result<()> {
+        self.buf_lines.clear();
+        self.set_buffers(bufs);
+        let current = match self.current_buffer.borrow().as_str() {
+            "" => String::from("core.weechat"),
+            name => String::from(name),
+        };
+        self.switch_current_buffer(&current).await?;
+        self.upgrading = false;
+        self.subscribe().await
+    }
+
+    /// Whether weechat is upgrading. Events are paused until it is done.
+    pub fn is_upgrading(&self) -> bool {
+        self.upgrading
+    }
+
+    /// Lag measured with pings, when enabled.
+    pub fn lag(&self) -> Option<Duration> {
+        self.pinger.as_ref()?.lag(Instant::now())
     }
 
     pub fn get_buffers(&self) -> &Vec<Buffer> {
         &self.bufs
     }
 
-    pub fn get_lines(&self) -> &Vec<LineData> {
-        &self.buf_lines
+    /// Return the lines to show for the current buffer.
+    pub fn get_lines(&self) -> &[LineData] {
+        if self.is_scrolling {
+            return &self.scrollback_lines;
+        }
+        self.get_current_buffer()
+            .and_then(|b| self.buf_lines.get(&b.ptr_buffer))
+            .map_or(&[], |l| l.lines())
+    }
+
+    /// Replace the buffer list, forgetting lines of closed buffers.
+    fn set_buffers(&mut self, bufs: Vec<Buffer>) {
+        self.buf_lines
+            .retain(|ptr, _| bufs.iter().any(|b| &b.ptr_buffer == ptr));
+        self.bufs = bufs;
+    }
+
+    /// Add a buffer from an opened event, shifting the buffers after it.
+    fn open_buffer(&mut self, buffer: Buffer) {
+        if self.bufs.iter().any(|b| b.ptr_buffer == buffer.ptr_buffer) {
+            return;
+        }
+        make_room(&mut self.bufs, buffer.number);
+        self.bufs.push(buffer);
+        self.bufs.sort_by_key(|b| b.number);
     }
 
-    pub async fn switch_current_buffer(&self, full_name: &String) -> Result<()> {
-        self.current_buffer.replace(full_name.clone());
+    /// Apply a buffer event other than opened to the buffer list.
+    fn update_buffer(&mut self, event: &str, change: BufferEvent) {
+        let index = match self
+            .bufs
+            .iter()
+            .position(|b| b.ptr_buffer == change.ptr_buffer)
+        {
+            Some(index) => index,
+            None => {
+                trace!("{} for unknown buffer {}", event, change.ptr_buffer);
+                return;
+            }
+        };
+        match event {
+            "_buffer_closing" => {
+                leave_number(&mut self.bufs, index);
+                self.bufs.remove(index);
+                self.buf_lines.remove(&change.ptr_buffer);
+                return;
+            }
+            "_buffer_moved" | "_buffer_unmerged" => {
+                leave_number(&mut self.bufs, index);
+                make_room(&mut self.bufs, change.number);
+            }
+            "_buffer_merged" => leave_number(&mut self.bufs, index),
+            "_buffer_hidden" => self.bufs[index].hidden = 1,
+            "_buffer_unhidden" => self.bufs[index].hidden = 0,
+            "_buffer_cleared" => {
+                self.buf_lines
+                    .insert(change.ptr_buffer, BufferLines::cleared());
+            }
+            _ => (),
+        }
+        let buffer = &mut self.bufs[index];
+        if buffer.full_name != change.full_name {
+            let mut current = self.current_buffer.borrow_mut();
+            if *current == buffer.full_name {
+                *current = change.full_name.clone();
+            }
+            buffer.full_name = change.full_name;
+        }
+        buffer.number = change.number;
+        if change.short_name.is_some() {
+            buffer.short_name = change.short_name;
+        }
+        if change.title.is_some() {
+            buffer.title = change.title;
+        }
+        if let Some(buffer_type) = change.buffer_type {
+            buffer.buffer_type = buffer_type;
+        }
+        if let Some(local_variables) = change.local_variables {
+            buffer.local_variables = local_variables;
+        }
+        self.bufs.sort_by_key(|b| b.number);
+    }
+
+    /// Return the nicklist of the current buffer, once received.
+    pub fn get_nicklist(&self) -> Option<&Nicklist> {
+        let current = self.get_current_buffer()?;
+        if self.nicklist.buffer == current.ptr_buffer {
+            Some(&self.nicklist)
+        } else {
+            None
+        }
+    }
+
+    /// Show another buffer, with its cached lines. Missing lines are fetched
+    /// in the background.
+    pub async fn switch_current_buffer(&mut self, full_name: &String) -> Result<()> {
+        let previous = self.current_buffer.replace(full_name.clone());
+        // buffers not synced fully are synced while shown.
+        if previous != *full_name {
+            if !previous.is_empty() && !self.is_synced(&previous) {
+                self.desync(&previous).await?;
+            }
+            if self.sync_profiles.get(full_name) != SyncProfile::Full {
+                self.send("sync", &format!("sync {}", full_name)).await?;
+            }
+        }
+        self.is_scrolling = false;
+        self.scrollback_lines.clear();
         if let Some(current) = self.get_current_buffer() {
+            let buffer = current.ptr_buffer;
+            let lines = self.buf_lines.get(&buffer);
+            if !matches!(lines, Some(l) if !l.needs_backfill()) {
+                self.request_background(
+                    &format!(
+                        "hdata buffer:{}/own_lines/last_line(-{})/data",
+                        buffer, BUFFER_CACHE_SIZE
+                    ),
+                    move |bl: messages::Hdata<LineData>| Response::Backlog(buffer, bl.hda),
+                );
+            }
+            // mark buffer as read
             self.send(
-                "backlog_lines",
-                format!(
-                    "hdata buffer:0x{}/own_lines/last_line(-{})/data",
-                    current.ptr_buffer, BUFFER_CACHE_SIZE
-                )
-                .as_str(),
+                "",
+                format!("input {} /buffer set hotlist -1", current.ptr_buffer).as_str(),
             )
-            .await
+            .await?;
+            self.hotlist().await?;
+            self.request_background(
+                &format!("nicklist {}", buffer),
+                move |nl: messages::Hdata<NicklistItem>| Response::Nicklist(buffer, nl.hda),
+            );
+            Ok(())
         } else {
             Ok(())
         }
     }
 
     pub async fn scroll_back(&self, scroll: usize) -> Result<()> {
-        if let Some(last_line) = self.buf_lines.get(
-            self.buf_lines
-                .len()
-                .checked_sub(1 + scroll)
-                .unwrap_or_default(),
-        ) {
-            if let Some(ref ptr_line) = last_line.ptr_line {
-                self.send(
-                    "scrollback_lines",
-                    format!("hdata line:0x{}(-{})/data", ptr_line, BUFFER_CACHE_SIZE).as_str(),
-                )
-                .await?;
-            } else {
-                self.switch_current_buffer(&self.current_buffer.borrow())
-                    .await?;
-            }
+        let lines = self.get_lines();
+        let last = lines.len().saturating_sub(scroll);
+        // lines received as events have no line pointer to start from.
+        let ptr_line = lines[..last].iter().rev().find_map(|l| l.ptr_line.as_ref());
+        if let (Some(ptr_line), Some(current)) = (ptr_line, self.get_current_buffer()) {
+            let buffer = current.ptr_buffer;
+            self.request_background(
+                &format!("hdata line:{}(-{})/data", ptr_line, BUFFER_CACHE_SIZE),
+                move |bl: messages::Hdata<LineData>| Response::Scrollback(buffer, bl.hda),
+            );
         }
         Ok(())
     }
@@ -142,12 +529,14 @@ impl Wee {
             .find(|b| b.full_name.as_str() == current_name.as_str())
     }
 
-    pub async fn close(&self) -> Result<()> {
+    /// Send the quit command and wait for it to be written.
+    pub async fn close(self) -> Result<()> {
         self.send_queue
             .0
             .send(String::from(""))
             .await
             .expect("Queueing close");
+        self.link.close().await;
         Ok(())
     }
 
@@ -161,11 +550,77 @@ impl Wee {
         Ok(())
     }
 
-    pub async fn buffers(&self) -> Result<()> {
-        // XXX it would be super nice to limit sent fields instead of receiving
-        // all that unused data.
-        self.send("gui_buffers", "hdata buffer:gui_buffers(*)")
-            .await
+    /// Send a command under a unique id, and return a future of its decoded
+    /// response.
+    ///
+    /// The response is only received while `run()` is polled. The future
+    /// fails if the connection is replaced before the response arrives.
+    pub fn request<T>(&self, command: &str) -> impl Future<Output = Result<T>>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let id = format!("req_{}", self.next_request.get());
+        self.next_request.set(self.next_request.get() + 1);
+        let (sender, receiver) = async_channel::bounded(1);
+        self.pending.borrow_mut().insert(id.clone(), sender);
+        self.send_queue
+            .0
+            .try_send(format!("({}) {}\n", id, command))
+            .expect("Queueing outgoing command");
+        async move {
+            let frame = receiver
+                .recv()
+                .await
+                .or(Err(Error::ProtocolError("request cancelled")))?;
+            Ok(de::from_bytes(&frame[..])?)
+        }
+    }
+
+    /// Send a request, whose response is handled by `run()` once converted
+    /// by `response`.
+    fn request_background<T, F>(&self, command: &str, response: F)
+    where
+        T: serde::de::DeserializeOwned + 'static,
+        F: FnOnce(T) -> Response + 'static,
+    {
+        let request = self.request(command);
+        self.responses
+            .push(async move { Ok(response(request.await?)) }.boxed_local());
+    }
+
+    /// Send a request and handle other messages until its response arrives.
+    async fn call<T>(&mut self, command: &str) -> Result<T>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let mut response = Box::pin(self.request(command).fuse());
+        loop {
+            select! {
+                res = response => return res,
+                incoming = self.run().fuse() => incoming?,
+            }
+        }
+    }
+
+    /// Request completion of the input `data` at `pos`, for the current
+    /// buffer.
+    pub fn complete(
+        &self,
+        pos: usize,
+        data: &str,
+    ) -> impl Future<Output = Result<Option<CompletionData>>> {
+        let response = self.get_current_buffer().map(|buf| {
+            self.request::<messages::CompletionResponse>(&format!(
+                "completion {} {} {}",
+                buf.ptr_buffer, pos, data
+            ))
+        });
+        async move {
+            match response {
+                Some(response) => Ok(response.await?.hda.into_iter().next()),
+                None => Ok(None),
+            }
+        }
     }
 
     pub async fn hotlist(&self) -> Result<()> {
@@ -173,130 +628,196 @@ impl Wee {
             .await
     }
 
-    /// Run and exchange messages.
+    /// Receive and handle one message. Commands are sent in the background.
+    ///
+    /// This is cancellation-safe, so it can be selected along other events.
+    ///
+    /// Pings are sent in the meantime, failing when one is not answered in
+    /// time, and the hotlist is fetched while some buffers are not synced.
     pub async fn run(&mut self) -> Result<()> {
-        // The blocks deserve an explanation...
-        // Considering this function is expected to be selected from a higher
-        // callsite. As such, the initial poll is cancelable, but as soon
-        // as we know there is data, we block. This ensures that reads
-        // and writes don't get cancelled mid-work and get all out of sync.
-        //
-        // The unpleasant side effect, is that the process will hang if
-        // the writes/reads block. The pleasant side-effect is that we don't
-        // have to keep state over partial reads, thus keeping everything
-        // simple. The other side effect, is that this future can be dropped
-        // to release the mutable borrow.
-        select! {
-            len = read_u32(&mut self.stream).fuse() => {
-                smol::block_on(self.handle_one(len? as usize))?;
-            },
-            outgoing = self.send_queue.1.recv().fuse() => {
-                let outgoing = outgoing.expect("Reading send queue");
-                if outgoing.len() == 0 {
-                    self.stream.write("(quit) quit\n".as_bytes()).await?;
-                } else {
-                    smol::block_on(self.stream.write(outgoing.as_bytes()))?;
-                }
-                while !self.send_queue.1.is_empty() {
-                    smol::block_on(
-                        self.stream.write(
-                            smol::block_on(self.send_queue.1.recv())
-                                .expect("Reading send queue").as_bytes())
-                    )?;
+        loop {
+            let ping_at = self.pinger.as_ref().map(|p| p.deadline());
+            let hotlist_at = if self.bufs.iter().any(|b| !self.is_synced(&b.full_name)) {
+                Some(self.next_hotlist)
+            } else {
+                None
+            };
+            let deadline = match (ping_at, hotlist_at) {
+                (Some(ping), Some(hotlist)) => Some(ping.min(hotlist)),
+                (ping, hotlist) => ping.or(hotlist),
+            };
+            let timer = async {
+                match deadline {
+                    Some(at) => {
+                        smol::Timer::at(at).await;
+                    }
+                    None => futures::future::pending().await,
                 }
-            },
-        };
+            };
+            select! {
+                frame = self.link.recv().fuse() => return self.handle_one(frame?).await,
+                response = self.responses.select_next_some() => {
+                    return self.handle_response(response?).await;
+                },
+                () = timer.fuse() => self.tick(Instant::now(), hotlist_at.is_some()).await?,
+            }
+        }
+    }
+
+    /// Send the ping and hotlist request due at `now`.
+    async fn tick(&mut self, now: Instant, fetch_hotlist: bool) -> Result<()> {
+        if let Some(pinger) = &mut self.pinger {
+            if let Some(arg) = pinger.tick(now)? {
+                self.send("", &format!("ping {}", arg)).await?;
+            }
+        }
+        if fetch_hotlist && now >= self.next_hotlist {
+            self.next_hotlist = now + HOTLIST_INTERVAL;
+            self.hotlist().await?;
+        }
+        Ok(())
+    }
+
+    async fn handle_response(&mut self, response: Response) -> Result<()> {
+        let current = self.get_current_buffer().map(|b| b.ptr_buffer);
+        match response {
+            // responses for buffers not shown anymore are stale.
+            Response::Nicklist(buffer, items) if current == Some(buffer) => {
+                self.nicklist = Nicklist::new(buffer, items);
+            }
+            Response::Nicklist(..) => (),
+            // closed buffers have no lines to fill.
+            Response::Backlog(buffer, lines)
+                if self.bufs.iter().any(|b| b.ptr_buffer == buffer) =>
+            {
+                // request was from end. reverse the list.
+                let lines = lines.into_iter().rev().collect();
+                self.buf_lines.entry(buffer).or_default().backfill(lines);
+            }
+            Response::Backlog(..) => (),
+            Response::Scrollback(buffer, lines) if current == Some(buffer) => {
+                self.is_scrolling = true;
+                self.scrollback_lines = lines.into_iter().rev().collect();
+            }
+            Response::Scrollback(..) => (),
+            Response::Buffers(bufs) => self.resync(bufs).await?,
+        }
         Ok(())
     }
 
-    async fn handle_one(&mut self, len: usize) -> Result<()> {
-        let mut comp = [0u8; 1];
-        self.stream.read_exact(&mut comp).await?;
-        assert_eq!(0, comp[0], "compression not implemented");
-        let mut buf = vec![0u8; len - 5];
-        self.stream.read_exact(&mut buf).await?;
+    async fn handle_one(&mut self, buf: Vec<u8>) -> Result<()> {
         let msg_id = de::peek_str(&buf)?;
         trace!("got message {:?}", msg_id);
+        let requester = msg_id.and_then(|id| self.pending.borrow_mut().remove(id));
+        if let Some(requester) = requester {
+            // the requester may have given up waiting, which is fine.
+            let _ = requester.try_send(buf);
+            return Ok(());
+        }
         match msg_id {
-            Some("gui_buffers") => {
-                let bufs: messages::BuffersResponse = de::from_bytes(&buf[..])?;
-                trace!("got buffers {:?}", bufs);
-                self.bufs = bufs.hda;
-            }
             Some("gui_hotlist") => {
                 let hl: messages::Hdata<messages::Hotlist> = de::from_bytes(&buf[..])?;
                 trace!("got hotlist {:?}", hl);
                 // clear existing hotlist
-                for mut b in &mut self.bufs {
+                for b in &mut self.bufs {
                     b.hotlist = (0, 0, 0, 0);
                 }
+                let profiles = &self.sync_profiles;
                 for hot in hl.hda {
-                    self.bufs
+                    if let Some(b) = self
+                        .bufs
                         .iter_mut()
                         .find(|b| b.ptr_buffer == hot.buffer)
-                        .map_or((), |b| {
-                            b.hotlist = hot.count;
-                        });
+                        .filter(|b| profiles.get(&b.full_name) != SyncProfile::None)
+                    {
+                        b.hotlist = hot.count;
+                    }
                 }
                 if self.current_buffer.borrow().as_str() == "" {
                     self.switch_current_buffer(&String::from("core.weechat"))
                         .await?;
                 }
             }
-            Some("backlog_lines") | Some("scrollback_lines") => {
-                self.is_scrolling = msg_id == Some("scrollback_lines");
-                self.buf_lines.clear();
-                let bl: messages::Hdata<LineData> = de::from_bytes(&buf[..])?;
-                // mark buffer as read
-                if bl.hda.len() > 0 && !self.is_scrolling {
-                    self.send_queue
-                        .0
-                        .send(format!(
-                            "input 0x{} /buffer set hotlist -1\n",
-                            bl.hda[0].buffer
-                        ))
-                        .await
-                        .expect("Queue message");
-                    self.hotlist().await.expect("requesting hotlist");
+            Some("_buffer_opened") => {
+                let opened: messages::Hdata<Buffer> = de::from_bytes(&buf[..])?;
+                for buffer in opened.hda {
+                    let name = buffer.full_name.clone();
+                    self.open_buffer(buffer);
+                    if self.is_synced(&name) {
+                        self.send("sync", &format!("sync {}", name)).await?;
+                    }
                 }
-
-                // buffer messages
-                for mut l in bl.hda {
-                    if let Some(prefix) = l.prefix {
-                        l.prefix = Some(strip_colors(prefix));
+            }
+            Some(event @ "_buffer_closing")
+            | Some(event @ "_buffer_renamed")
+            | Some(event @ "_buffer_title_changed")
+            | Some(event @ "_buffer_type_changed")
+            | Some(event @ "_buffer_moved")
+            | Some(event @ "_buffer_merged")
+            | Some(event @ "_buffer_unmerged")
+            | Some(event @ "_buffer_hidden")
+            | Some(event @ "_buffer_unhidden")
+            | Some(event @ "_buffer_localvar_added")
+            | Some(event @ "_buffer_localvar_changed")
+            | Some(event @ "_buffer_localvar_removed")
+            | Some(event @ "_buffer_cleared") => {
+                let changes: messages::Hdata<BufferEvent> = de::from_bytes(&buf[..])?;
+                for change in changes.hda {
+                    let name = change.full_name.clone();
+                    self.update_buffer(event, change);
+                    // buffers are synced by name.
+                    if event == "_buffer_renamed" && self.is_synced(&name) {
+                        self.send("sync", &format!("sync {}", name)).await?;
                     }
-                    l.message = strip_colors(l.message);
-                    self.buf_lines.insert(0, l); // request was from end. reverse the list.
                 }
             }
-            Some("_buffer_opened")
-            | Some("_buffer_closing")
-            | Some("_buffer_renamed")
-            | Some("_buffer_title_changed") => {
-                self.buffers().await?;
-                self.hotlist().await?;
+            Some("_pong") => {
+                let pong: messages::Pong = de::from_bytes(&buf[..])?;
+                if let Some(pinger) = &mut self.pinger {
+                    pinger.pong(pong.str.as_deref().unwrap_or(""), Instant::now());
+                }
+            }
+            Some("_upgrade") => {
+                info!("WeeChat is upgrading");
+                self.upgrading = true;
+                // keep the upgrade flag, to be told when it ends. Buffers
+                // synced by name are desynced by name.
+                self.send("", "desync * buffers,buffer,nicklist").await?;
+                if let Some(names) = self.synced_names() {
+                    self.send("", &format!("desync {}", names)).await?;
+                }
+            }
+            Some("_upgrade_ended") => {
+                info!("WeeChat upgraded, resyncing");
+                self.request_background(
+                    "hdata buffer:gui_buffers(*)",
+                    |bufs: messages::BuffersResponse| Response::Buffers(bufs.hda),
+                );
+            }
+            Some("_nicklist") => {
+                let nl: messages::Hdata<NicklistItem> = de::from_bytes(&buf[..])?;
+                let current = self.get_current_buffer().map(|b| b.ptr_buffer);
+                // events for other buffers are ignored.
+                if let Some(buffer) = nl.hda.first().map(|i| i.ptr_buffer) {
+                    if current == Some(buffer) {
+                        self.nicklist = Nicklist::new(buffer, nl.hda);
+                    }
+                }
+            }
+            Some("_nicklist_diff") => {
+                let diff: messages::Hdata<NicklistItem> = de::from_bytes(&buf[..])?;
+                if matches!(diff.hda.first(), Some(i) if i.ptr_buffer == self.nicklist.buffer) {
+                    self.nicklist.apply_diff(diff.hda);
+                }
             }
             Some("_buffer_line_added") => {
-                let mut msg: messages::LineAddedEvent = de::from_bytes(&buf[..])?;
+                let msg: messages::LineAddedEvent = de::from_bytes(&buf[..])?;
+                let line = msg.hda.0;
                 let current = self.current_buffer.borrow();
-                for mut buf in self.bufs.iter_mut() {
-                    {
-                        if buf.ptr_buffer.as_str() != msg.hda.0.buffer.as_str() {
-                            continue;
-                        }
-                    }
-                    if buf.full_name.as_str() == current.as_str() && !self.is_scrolling {
-                        // add to current lines
-                        if buf.ptr_buffer == msg.hda.0.buffer {
-                            if let Some(prefix) = msg.hda.0.prefix {
-                                msg.hda.0.prefix = Some(strip_colors(prefix));
-                            }
-                            msg.hda.0.message = strip_colors(msg.hda.0.message);
-                            self.buf_lines.push(msg.hda.0);
-                        }
-                    } else {
+                if let Some(buf) = self.bufs.iter_mut().find(|b| b.ptr_buffer == line.buffer) {
+                    if buf.full_name.as_str() != current.as_str() || self.is_scrolling {
                         // increment hotlist
-                        match msg.hda.0.notify_level {
+                        match line.notify_level {
                             0 => buf.hotlist.0 += 1,
                             1 => buf.hotlist.1 += 1,
                             2 => buf.hotlist.2 += 1,
@@ -304,15 +825,7 @@ impl Wee {
                             _ => (),
                         }
                     }
-                    break;
-                }
-                trace!("{:?}", self.bufs);
-            }
-            Some("completion") => {
-                let mut msg: messages::CompletionResponse = de::from_bytes(&buf[..])?;
-                log::trace!("completion: {:?}", msg);
-                if msg.hda.len() > 0 {
-                    self.completion.replace(Some(msg.hda.remove(0)));
+                    self.buf_lines.entry(line.buffer).or_default().push(line);
                 }
             }
             msg_id => {
@@ -322,59 +835,207 @@ impl Wee {
         }
         Ok(())
     }
+}
 
-    async fn auth(&mut self, pass: &str) -> Result<()> {
-        self.stream
-            .write(
-                format!(
-                    "(handshake) handshake compression=off,password_hash_algo={}\n",
-                    auth::SUPPORTED_HASHES
-                )
-                .as_bytes(),
+/// Do the handshake and authentication, and check the relay answers.
+async fn auth(
+    stream: &mut Box<dyn Stream>,
+    conf: &crate::config::Conf,
+    totp_prompt: Option<TotpPrompt>,
+) -> Result<()> {
+    stream
+        .write(
+            format!(
+                "(handshake) handshake compression={},password_hash_algo={}\n",
+                conf.compression,
+                auth::SUPPORTED_HASHES
             )
-            .await?;
-        let res: messages::HandshakeResponse = get_message(&mut self.stream).await?;
-        assert_eq!("handshake", res.id, "expected handshake response");
-        trace!("handshake response: {:?}", res);
-
-        trace!("Sending auth");
-        let auth = format!(
-            "init {}\n",
-            auth::create_auth(res.htb.borrow().into(), pass),
-        );
-        self.stream.write(auth.as_bytes()).await?;
-
-        trace!("checking version info");
-        self.stream.write(b"(version_check) info version\n").await?;
-        let received: messages::Info = get_message(&mut self.stream).await.or_else(|e| {
-            Err(match e {
-                Error::PacketError {
-                    source: de::Error::Eof,
-                } => Error::ProtocolError("Connection unexpectedly closed. Check password."),
-                e => e,
-            })
-        })?;
-        info!("Server version {:?}", received.inf.1);
-        Ok(())
+            .as_bytes(),
+        )
+        .await?;
+    let res: messages::HandshakeResponse = get_message(stream).await?;
+    assert_eq!("handshake", res.id, "expected handshake response");
+    trace!("handshake response: {:?}", res);
+    // frames are decoded by their own compression flag.
+    info!("Using compression {}", res.htb.compression);
+
+    trace!("Sending auth");
+    let mut auth = format!(
+        "init {}",
+        auth::create_auth(res.htb.borrow().try_into()?, conf.password.as_str())?,
+    );
+    let use_totp = res.htb.totp == "on";
+    if use_totp {
+        let code = match conf.totp_secret {
+            Some(ref secret) => totp::generate(secret)?,
+            None => match totp_prompt {
+                Some(prompt) => prompt()?,
+                None => return Err(Error::TotpRequired),
+            },
+        };
+        auth.push_str(format!(",totp={}", code.trim()).as_str());
     }
+    auth.push('\n');
+    stream.write(auth.as_bytes()).await?;
 
-    /// Return the current completion data.
-    pub fn consume_completion(&self) -> Option<messages::CompletionData> {
-        self.completion.replace(None)
+    trace!("checking version info");
+    stream.write(b"(version_check) info version\n").await?;
+    // The relay drops the connection on authentication failure.
+    let received: messages::Info = get_message(stream).await.map_err(|e| match e {
+        e if !e.is_eof() => e,
+        _ if use_totp => {
+            Error::ProtocolError("Connection unexpectedly closed. Check password and TOTP code.")
+        }
+        _ => Error::ProtocolError("Connection unexpectedly closed. Check password."),
+    })?;
+    info!("Server version {:?}", received.inf.1);
+    Ok(())
+}
+
+/// Relay address, parsed from the `host` and `port` settings.
+#[derive(Debug, PartialEq)]
+enum Address<'a> {
+    Tcp {
+        host: &'a str,
+        port: u16,
+    },
+    /// From a unix: address or the socket path setting.
+    Unix(&'a Path),
+    /// From a ws:// or wss:// URL.
+    WebSocket {
+        host: &'a str,
+        port: u16,
+        path: &'a str,
+        secure: bool,
+    },
+}
+
+fn parse_address(host: &str, port: Option<u16>) -> Result<Address<'_>> {
+    if host.is_empty() {
+        return Err(Error::ConfigError("missing relay host"));
+    }
+    if let Some(path) = host.strip_prefix("unix:") {
+        return Ok(Address::Unix(Path::new(path)));
+    }
+    let (url, secure) = match (host.strip_prefix("ws://"), host.strip_prefix("wss://")) {
+        (Some(url), _) => (url, false),
+        (_, Some(url)) => (url, true),
+        _ => {
+            let port = port.ok_or(Error::ConfigError("missing relay port"))?;
+            return Ok(Address::Tcp { host, port });
+        }
+    };
+    let (authority, path) = match url.find('/') {
+        Some(pos) => url.split_at(pos),
+        None => (url, "/"),
+    };
+    let (host, url_port) = match authority.rfind(':') {
+        // not part of an IPv6 address
+        Some(pos) if !authority[pos..].contains(']') => {
+            let url_port = authority[pos + 1..]
+                .parse()
+                .or(Err(Error::ConfigError("invalid port in relay URL")))?;
+            (&authority[..pos], Some(url_port))
+        }
+        _ => (authority, None),
+    };
+    if host.is_empty() {
+        return Err(Error::ConfigError("missing host in relay URL"));
+    }
+    let default_port = if secure { 443 } else { 80 };
+    Ok(Address::WebSocket {
+        host,
+        port: url_port.unwrap_or(default_port),
+        path,
+        secure,
+    })
+}
+
+async fn connect(conf: &crate::config::Conf) -> Result<Box<dyn Stream>> {
+    let address = match &conf.socket_path {
+        Some(path) => Address::Unix(path),
+        None => parse_address(&conf.host, conf.port)?,
+    };
+    match address {
+        Address::Tcp { host, port } => connect_tcp(conf, host, port, conf.ssl).await,
+        Address::Unix(path) => {
+            trace!("connecting to {:?}", path);
+            // the socket is local, TLS is not used.
+            let stream = Async::<std::os::unix::net::UnixStream>::connect(path).await?;
+            Ok(Box::new(stream))
+        }
+        Address::WebSocket {
+            host,
+            port,
+            path,
+            secure,
+        } => {
+            let stream = connect_tcp(conf, host, port, secure).await?;
+            let authority = match (secure, port) {
+                (false, 80) | (true, 443) => String::from(host),
+                _ => format!("{}:{}", host, port),
+            };
+            let ws = websocket::connect(stream, &authority, path, &conf.headers).await?;
+            Ok(Box::new(ws))
+        }
     }
 }
 
-async fn connect(host: &str, port: u16, ssl: bool, insecure: bool) -> Result<Box<dyn Stream>> {
+async fn connect_tcp(
+    conf: &crate::config::Conf,
+    host: &str,
+    port: u16,
+    ssl: bool,
+) -> Result<Box<dyn Stream>> {
     trace!("creating stream");
-    let stream = Async::new(TcpStream::connect((host, port))?)?;
+    // brackets are only needed in URLs.
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let stream = match &conf.proxy {
+        Some(proxy) => Box::new(proxy::connect(proxy, host, port).await?),
+        None => Box::new(open_tcp(host, port).await?),
+    };
 
     if ssl {
-        trace!("doing tls handshake");
-        let connector =
-            async_native_tls::TlsConnector::new().danger_accept_invalid_hostnames(insecure);
-        Ok(Box::new(connector.connect(host, stream).await?))
+        tls::connect(conf, host, stream).await
     } else {
-        Ok(Box::new(stream))
+        Ok(stream)
+    }
+}
+
+/// Open a TCP connection without blocking, resolving `host` in the
+/// background.
+async fn open_tcp(host: &str, port: u16) -> Result<Async<TcpStream>> {
+    let target = (String::from(host), port);
+    let addrs =
+        smol::unblock(move || target.to_socket_addrs().map(|a| a.collect::<Vec<_>>())).await?;
+    let mut error = None;
+    for addr in addrs {
+        match Async::<TcpStream>::connect(addr).await {
+            Ok(stream) => return Ok(stream),
+            Err(e) => error = Some(e),
+        }
+    }
+    Err(error
+        .unwrap_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "no address"))
+        .into())
+}
+
+/// Shift the buffers at `number` and after, to insert a buffer there.
+fn make_room(bufs: &mut [Buffer], number: i32) {
+    for b in bufs.iter_mut().filter(|b| b.number >= number) {
+        b.number += 1;
+    }
+}
+
+/// Free the number of a buffer before it moves or closes. Buffers after it
+/// are shifted back when it was not merged with others.
+fn leave_number(bufs: &mut [Buffer], index: usize) {
+    let number = bufs[index].number;
+    bufs[index].number = 0;
+    if !bufs.iter().any(|b| b.number == number) {
+        for b in bufs.iter_mut().filter(|b| b.number > number) {
+            b.number -= 1;
+        }
     }
 }
 
@@ -393,91 +1054,262 @@ where
     T: serde::de::DeserializeOwned,
 {
     let len = read_u32(stream).await? as usize;
+    let buf = read_body(stream, len).await?;
+    Ok(de::from_bytes(&buf[..])?)
+}
+
+/// Read the remainder of a message of `len` bytes, following the length
+/// header, and return its decompressed content.
+async fn read_body<S>(stream: &mut S, len: usize) -> Result<Vec<u8>>
+where
+    S: AsyncReadExt + std::marker::Unpin,
+{
+    let len = len
+        .checked_sub(5)
+        .ok_or(Error::ProtocolError("invalid message length"))?;
+    if len > link::MAX_FRAME_SIZE {
+        return Err(Error::ProtocolError("message too long"));
+    }
     let mut comp = [0u8; 1];
     stream.read_exact(&mut comp).await?;
-    assert_eq!(0, comp[0], "compression not implemented");
-    let mut buf = vec![0u8; len - 5];
+    let mut buf = vec![0u8; len];
     stream.read_exact(&mut buf).await?;
-    Ok(de::from_bytes(&buf[..])?)
+    compression::decompress(comp[0], buf)
 }
 
-/// strip weechat colors from string
-fn strip_colors(input: String) -> String {
-    let mut output = String::new();
-    let mut it = input.chars().peekable();
-    loop {
-        let c = match it.next() {
-            None => break,
-            Some(c) => c,
-        };
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        if c == '\x1c' {
-        } else if c == '\x19' {
-            if let 'F' | 'B' = it.peek().unwrap() {
-                it.next();
-            }
-            if let '*' | '!' | '/' | '_' | '|' = it.peek().unwrap() {
-                it.next();
-            }
-            match it.peek().unwrap() {
-                '@' => {
-                    for _ in 0..6 {
-                        it.next();
-                    }
-                }
-                _ => {
-                    it.next();
-                    it.next();
-                }
-            }
-            match it.peek() {
-                Some('~') | Some(',') => {
-                    it.next();
-                    match it.peek().unwrap() {
-                        '@' => {
-                            for _ in 0..6 {
-                                it.next();
-                            }
-                        }
-                        _ => {
-                            it.next();
-                            it.next();
-                        }
-                    }
-                }
-                _ => (),
+    /// Version info responses, as recorded from a relay for each compression.
+    const RECORDED_FRAMES: [&[u8]; 3] = [
+        b"\x00\x00\x00\x2b\x00\x00\x00\x00\rversion_checkinf\x00\x00\x00\x07version\
+          \x00\x00\x00\x032.9",
+        b"\x00\x00\x00\x2c\x01\x78\x9c\x63\x60\x60\xe0\x2d\x4b\x2d\x2a\xce\xcc\xcf\x8b\
+          \x4f\xce\x48\x4d\xce\xce\xcc\x4b\x63\x60\x60\x60\x87\x8a\x01\x99\xcc\x46\x7a\x96\
+          \x00\xd5\x34\x0a\x57",
+        b"\x00\x00\x00\x34\x02\x28\xb5\x2f\xfd\x00\x58\x31\x01\x00\x00\x00\x00\rversion_\
+          checkinf\x00\x00\x00\x07version\x00\x00\x00\x032.9",
+    ];
+
+    /// Build an uncompressed info response frame.
+    fn info_frame(id: &str, value: &str) -> Vec<u8> {
+        let mut body = vec![];
+        for (i, s) in [id, "version", value].iter().enumerate() {
+            if i == 1 {
+                body.extend_from_slice(b"inf");
             }
-        } else {
-            output.push(c);
+            body.extend_from_slice(&(s.len() as u32).to_be_bytes());
+            body.extend_from_slice(s.as_bytes());
         }
+        let mut frame = ((body.len() + 5) as u32).to_be_bytes().to_vec();
+        frame.push(0);
+        frame.extend(body);
+        frame
     }
-    output
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_request_correlation() {
+        smol::block_on(async {
+            let (client, mut server) = smol::net::unix::UnixStream::pair().unwrap();
+            let mut wee = Wee::new(Box::new(client));
+            let first = wee.request::<messages::Info>("info version");
+            let second = wee.request::<messages::Info>("info version");
+
+            let expected = b"(req_0) info version\n(req_1) info version\n";
+            let mut sent = vec![0u8; expected.len()];
+            server.read_exact(&mut sent).await.unwrap();
+            assert_eq!(&expected[..], &sent[..]);
+
+            // answer out of order, with an unrelated message in between.
+            server.write_all(&info_frame("req_1", "2")).await.unwrap();
+            server.write_all(&info_frame("req_42", "?")).await.unwrap();
+            server.write_all(&info_frame("req_0", "1")).await.unwrap();
+            for _ in 0..3 {
+                wee.run().await.unwrap();
+            }
+            assert_eq!(Some(String::from("2")), second.await.unwrap().inf.1);
+            assert_eq!(Some(String::from("1")), first.await.unwrap().inf.1);
+            assert!(wee.pending.borrow().is_empty());
+        });
+    }
 
     #[test]
-    fn test_strip_colors() {
-        let inputs = [
-            "\x1904foobar",
-            "foo\x1901bar",
-            "foo\x19F01bar",
-            "foo\x19B22bar",
-            "foo\x19F@12345bar",
-            "foo\x19@12345,23bar",
-            "foo\x19@12345,@12345bar",
-            "foo\x19@12345~@12345bar",
-            "foo\x19*@12345~@12345bar",
-        ];
-        for input in inputs.iter() {
-            assert_eq!(
-                String::from("foobar"),
-                strip_colors(String::from(*input)),
-                "Stripping colors from {:?}",
-                input
-            );
+    fn test_read_compressed_frames() {
+        for frame in RECORDED_FRAMES.iter() {
+            let mut stream = futures::io::Cursor::new(frame);
+            let msg: messages::Info = smol::block_on(get_message(&mut stream)).unwrap();
+            assert_eq!("version_check", msg.id);
+            assert_eq!(("version".into(), Some("2.9".into())), msg.inf);
         }
     }
+
+    #[test]
+    fn test_read_truncated_frame() {
+        let mut stream = futures::io::Cursor::new(&RECORDED_FRAMES[1][..20]);
+        let res: Result<messages::Info> = smol::block_on(get_message(&mut stream));
+        assert!(res.is_err());
+    }
+
+    fn buffer(ptr: u64, number: i32) -> Buffer {
+        Buffer {
+            ptr_buffer: Pointer(ptr),
+            number,
+            short_name: None,
+            full_name: format!("buffer{}", ptr),
+            title: None,
+            nicklist_display_groups: 1,
+            buffer_type: 0,
+            hidden: 0,
+            local_variables: HashMap::new(),
+            hotlist: (0, 0, 0, 0),
+        }
+    }
+
+    fn change(ptr: u64, number: i32) -> BufferEvent {
+        BufferEvent {
+            ptr_buffer: Pointer(ptr),
+            number,
+            full_name: format!("buffer{}", ptr),
+            short_name: None,
+            title: None,
+            buffer_type: None,
+            local_variables: None,
+        }
+    }
+
+    #[test]
+    fn test_update_buffer() {
+        let (client, _server) = smol::net::unix::UnixStream::pair().unwrap();
+        let mut wee = Wee::new(Box::new(client));
+        wee.set_buffers(vec![buffer(1, 1), buffer(2, 2), buffer(3, 3)]);
+        let numbers = |w: &Wee| -> Vec<(u64, i32)> {
+            w.get_buffers()
+                .iter()
+                .map(|b| (b.ptr_buffer.0, b.number))
+                .collect()
+        };
+
+        wee.update_buffer("_buffer_merged", change(3, 1));
+        assert_eq!(vec![(1, 1), (3, 1), (2, 2)], numbers(&wee));
+        wee.update_buffer("_buffer_unmerged", change(3, 2));
+        assert_eq!(vec![(1, 1), (3, 2), (2, 3)], numbers(&wee));
+        wee.update_buffer("_buffer_moved", change(1, 3));
+        assert_eq!(vec![(3, 1), (2, 2), (1, 3)], numbers(&wee));
+
+        wee.update_buffer("_buffer_hidden", change(2, 2));
+        assert_eq!(1, wee.get_buffers()[1].hidden);
+        let mut localvar = change(2, 2);
+        localvar.local_variables = Some(
+            vec![(String::from("type"), String::from("channel"))]
+                .into_iter()
+                .collect(),
+        );
+        wee.update_buffer("_buffer_localvar_added", localvar);
+        assert_eq!("channel", wee.get_buffers()[1].local_variables["type"]);
+
+        // events for unknown buffers are ignored.
+        wee.update_buffer("_buffer_closing", change(4, 4));
+        wee.update_buffer("_buffer_closing", change(3, 1));
+        assert_eq!(vec![(2, 1), (1, 2)], numbers(&wee));
+    }
+
+    #[test]
+    fn test_empty_backlog() {
+        let (client, _server) = smol::net::unix::UnixStream::pair().unwrap();
+        let mut wee = Wee::new(Box::new(client));
+        wee.set_buffers(vec![buffer(1, 1)]);
+        wee.buf_lines.entry(Pointer(1)).or_default().disconnected();
+        // an empty reply still closes the gap.
+        smol::block_on(wee.handle_response(Response::Backlog(Pointer(1), vec![]))).unwrap();
+        assert!(!wee.buf_lines[&Pointer(1)].needs_backfill());
+        // replies for closed buffers are dropped.
+        smol::block_on(wee.handle_response(Response::Backlog(Pointer(2), vec![]))).unwrap();
+        assert!(!wee.buf_lines.contains_key(&Pointer(2)));
+    }
+
+    #[test]
+    fn test_stale_scrollback() {
+        let (client, _server) = smol::net::unix::UnixStream::pair().unwrap();
+        let mut wee = Wee::new(Box::new(client));
+        wee.set_buffers(vec![buffer(1, 1), buffer(2, 2)]);
+        wee.current_buffer.replace(String::from("buffer1"));
+        // replies for a buffer not shown anymore are dropped.
+        smol::block_on(wee.handle_response(Response::Scrollback(Pointer(2), vec![]))).unwrap();
+        assert!(!wee.is_scrolling);
+        smol::block_on(wee.handle_response(Response::Scrollback(Pointer(1), vec![]))).unwrap();
+        assert!(wee.is_scrolling);
+    }
+
+    #[test]
+    fn test_ping_timeout() {
+        smol::block_on(async {
+            let (client, mut server) = smol::net::unix::UnixStream::pair().unwrap();
+            let mut wee = Wee::new(Box::new(client));
+            let interval = Duration::from_millis(10);
+            wee.pinger = Some(Pinger::new(interval, interval));
+            // the relay never answers.
+            let err = wee.run().await.unwrap_err();
+            assert!(err.is_disconnect());
+            let mut sent = vec![0u8; 17];
+            server.read_exact(&mut sent).await.unwrap();
+            assert_eq!(&b"() ping weesels1\n"[..], &sent[..]);
+        });
+    }
+
+    #[test]
+    fn test_parse_address() {
+        assert_eq!(
+            Address::Tcp {
+                host: "relay.example",
+                port: 9000
+            },
+            parse_address("relay.example", Some(9000)).unwrap()
+        );
+        assert!(parse_address("relay.example", None).is_err());
+        assert!(parse_address("", Some(9000)).is_err());
+        assert_eq!(
+            Address::Unix(Path::new("/run/weechat/relay.sock")),
+            parse_address("unix:/run/weechat/relay.sock", None).unwrap()
+        );
+        assert_eq!(
+            Address::WebSocket {
+                host: "relay.example",
+                port: 443,
+                path: "/weechat",
+                secure: true
+            },
+            parse_address("wss://relay.example/weechat", None).unwrap()
+        );
+        assert_eq!(
+            Address::WebSocket {
+                host: "relay.example",
+                port: 443,
+                path: "/weechat",
+                secure: true
+            },
+            parse_address("wss://relay.example/weechat", Some(9000)).unwrap()
+        );
+        assert_eq!(
+            Address::WebSocket {
+                host: "[::1]",
+                port: 8080,
+                path: "/",
+                secure: false
+            },
+            parse_address("ws://[::1]:8080", Some(9000)).unwrap()
+        );
+        assert_eq!(
+            Address::WebSocket {
+                host: "relay.example",
+                port: 80,
+                path: "/",
+                secure: false
+            },
+            // the port setting is for the host name, not URLs.
+            parse_address("ws://relay.example", Some(9000)).unwrap()
+        );
+        assert!(parse_address("ws://relay.example:http/", None).is_err());
+        assert!(parse_address("wss:///weechat", None).is_err());
+    }
 }
@@ -0,0 +1,247 @@
+use super::messages::{NicklistItem, Pointer};
+
+/// Nicklist of a buffer, kept as a flat list of groups and nicks.
+#[derive(Default, Debug)]
+pub struct Nicklist {
+    /// Pointer of the buffer the nicklist belongs to.
+    pub buffer: Pointer,
+    entries: Vec<Entry>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    /// Name of the parent group, None for the root group.
+    parent: Option<String>,
+    item: NicklistItem,
+}
+
+impl Entry {
+    fn is(&self, parent: &Option<String>, item: &NicklistItem) -> bool {
+        &self.parent == parent && self.item.group == item.group && self.item.name == item.name
+    }
+}
+
+impl Nicklist {
+    /// Build a nicklist from a full nicklist hdata, which is in tree order.
+    pub fn new(buffer: Pointer, items: Vec<NicklistItem>) -> Self {
+        // open groups with their level, the nicks being in the last one.
+        let mut groups: Vec<(i32, String)> = vec![];
+        let mut entries = vec![];
+        for item in items {
+            if item.group != 0 {
+                while matches!(groups.last(), Some((level, _)) if *level >= item.level) {
+                    groups.pop();
+                }
+            }
+            let parent = groups.last().map(|(_, name)| name.clone());
+            if item.group != 0 {
+                groups.push((item.level, item.name.clone()));
+            }
+            entries.push(Entry { parent, item });
+        }
+        Nicklist { buffer, entries }
+    }
+
+    /// Apply a `_nicklist_diff` event.
+    pub fn apply_diff(&mut self, items: Vec<NicklistItem>) {
+        let mut parent = None;
+        for item in items {
+            match item.diff as u8 {
+                b'^' => parent = Some(item.name),
+                b'+' => self.entries.push(Entry {
+                    parent: parent.clone(),
+                    item,
+                }),
+                b'-' => self.entries.retain(|e| !e.is(&parent, &item)),
+                b'*' => {
+                    if let Some(e) = self.entries.iter_mut().find(|e| e.is(&parent, &item)) {
+                        e.item = item;
+                    }
+                }
+                diff => log::trace!("unknown nicklist diff {:?}", diff),
+            }
+        }
+    }
+
+    /// Return the visible groups and nicks with their depth, grouped and
+    /// sorted like weechat: sub-groups first, then nicks, each sorted by name.
+    pub fn lines(&self, display_groups: bool) -> Vec<(usize, &NicklistItem)> {
+        let mut lines = vec![];
+        for root in self.entries.iter().filter(|e| e.parent.is_none()) {
+            self.push_group(&root.item, 0, display_groups, &mut lines);
+        }
+        lines
+    }
+
+    fn push_group<'a>(
+        &'a self,
+        group: &'a NicklistItem,
+        depth: usize,
+        display_groups: bool,
+        lines: &mut Vec<(usize, &'a NicklistItem)>,
+    ) {
+        let depth = if group.visible != 0 && display_groups {
+            lines.push((depth, group));
+            depth + 1
+        } else {
+            depth
+        };
+        let mut children: Vec<&NicklistItem> = self
+            .entries
+            .iter()
+            .filter(|e| e.parent.as_ref() == Some(&group.name))
+            .map(|e| &e.item)
+            .collect();
+        children.sort_by_key(|i| (i.group == 0, i.name.to_lowercase()));
+        for child in children {
+            if child.group != 0 {
+                self.push_group(child, depth, display_groups, lines);
+            } else if child.visible != 0 {
+                lines.push((depth, child));
+            }
+        }
+    }
+}
+
+/// Strip the sort prefix of a group name (e.g. "000|o").
+pub fn group_name(name: &str) -> &str {
+    match name.find('|') {
+        Some(pos) if name[..pos].bytes().all(|b| b.is_ascii_digit()) => &name[pos + 1..],
+        _ => name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{de, messages::Hdata};
+    use super::*;
+
+    fn item(diff: u8, group: bool, level: i32, name: &str, prefix: &str) -> NicklistItem {
+        NicklistItem {
+            ptr_buffer: Pointer(0x1234),
+            diff: diff as i8,
+            group: group as i8,
+            visible: if name == "root" { 0 } else { 1 },
+            level,
+            name: String::from(name),
+            color: None,
+            prefix: Some(String::from(prefix)),
+            prefix_color: None,
+        }
+    }
+
+    fn names(nicklist: &Nicklist, display_groups: bool) -> Vec<(usize, &str)> {
+        nicklist
+            .lines(display_groups)
+            .into_iter()
+            .map(|(depth, i)| (depth, i.name.as_str()))
+            .collect()
+    }
+
+    fn channel() -> Nicklist {
+        Nicklist::new(
+            Pointer(0x1234),
+            vec![
+                item(0, true, 0, "root", ""),
+                item(0, true, 1, "001|v", ""),
+                item(0, false, 0, "Zed", "+"),
+                item(0, true, 1, "000|o", ""),
+                item(0, false, 0, "bob", "@"),
+                item(0, false, 0, "alice", "@"),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_sorted_lines() {
+        let nicklist = channel();
+        assert_eq!(
+            vec![(0, "alice"), (0, "bob"), (0, "Zed")],
+            names(&nicklist, false)
+        );
+        assert_eq!(
+            vec![
+                (0, "000|o"),
+                (1, "alice"),
+                (1, "bob"),
+                (0, "001|v"),
+                (1, "Zed")
+            ],
+            names(&nicklist, true)
+        );
+    }
+
+    #[test]
+    fn test_nested_groups() {
+        let nicklist = Nicklist::new(
+            Pointer(0x1234),
+            vec![
+                item(0, true, 0, "root", ""),
+                item(0, true, 1, "ops", ""),
+                item(0, true, 2, "bots", ""),
+                item(0, false, 0, "ci", ""),
+                item(0, true, 3, "old", ""),
+                item(0, true, 1, "users", ""),
+                item(0, false, 0, "alice", ""),
+            ],
+        );
+        assert_eq!(
+            vec![
+                (0, "ops"),
+                (1, "bots"),
+                (2, "old"),
+                (2, "ci"),
+                (0, "users"),
+                (1, "alice")
+            ],
+            names(&nicklist, true)
+        );
+    }
+
+    #[test]
+    fn test_apply_diff() {
+        let mut nicklist = channel();
+        nicklist.apply_diff(vec![
+            item(b'^', true, 1, "000|o", ""),
+            item(b'-', false, 0, "bob", "@"),
+            item(b'^', true, 1, "001|v", ""),
+            item(b'+', false, 0, "bob", "+"),
+            item(b'*', false, 0, "Zed", "%"),
+        ]);
+        assert_eq!(
+            vec![(0, "alice"), (0, "bob"), (0, "Zed")],
+            names(&nicklist, false)
+        );
+        let prefixes: Vec<_> = nicklist
+            .lines(false)
+            .into_iter()
+            .map(|(_, i)| i.prefix.clone().unwrap())
+            .collect();
+        assert_eq!(vec!["@", "+", "%"], prefixes);
+    }
+
+    #[test]
+    fn test_decode_diff() {
+        let encoded = b"\0\0\0\x0e_nicklist_diffhda\0\0\0\x14buffer/nicklist_item\
+            \0\0\0\x58_diff:chr,group:chr,visible:chr,level:int,name:str,color:str,\
+            prefix:str,prefix_color:str\0\0\0\x02\
+            \x041234\x04abcd^\x01\x01\0\0\0\x01\0\0\0\x05000|o\0\0\0\x0bweechat.col\
+            \xff\xff\xff\xff\xff\xff\xff\xff\
+            \x041234\x04abce+\0\x01\0\0\0\0\0\0\0\x05alice\0\0\0\x03red\
+            \0\0\0\x01@\0\0\0\x0alightgreen";
+        let diff: Hdata<NicklistItem> = de::from_bytes(encoded).unwrap();
+        assert_eq!(2, diff.hda.len());
+        assert_eq!(b'^' as i8, diff.hda[0].diff);
+        assert_eq!(Pointer(0x1234), diff.hda[1].ptr_buffer);
+        assert_eq!("alice", diff.hda[1].name);
+        assert_eq!(Some(String::from("@")), diff.hda[1].prefix);
+        assert_eq!(Some(String::from("lightgreen")), diff.hda[1].prefix_color);
+    }
+
+    #[test]
+    fn test_group_name() {
+        assert_eq!("o", group_name("000|o"));
+        assert_eq!("a|b", group_name("a|b"));
+        assert_eq!("root", group_name("root"));
+    }
+}
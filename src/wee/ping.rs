@@ -0,0 +1,124 @@
+//! Lag measurement with relay pings, also detecting dead connections.
+use super::Result;
+use crate::config::Conf;
+use std::time::{Duration, Instant};
+
+/// Schedule of pings, and lag measured from their pongs.
+#[derive(Debug)]
+pub struct Pinger {
+    interval: Duration,
+    timeout: Duration,
+    next_ping: Instant,
+    /// Argument and time of the ping waiting for its pong.
+    pending: Option<(String, Instant)>,
+    count: u64,
+    lag: Option<Duration>,
+}
+
+impl Pinger {
+    pub fn new(interval: Duration, timeout: Duration) -> Self {
+        Pinger {
+            interval,
+            timeout,
+            next_ping: Instant::now() + interval,
+            pending: None,
+            count: 0,
+            lag: None,
+        }
+    }
+
+    /// Pinger configured by `conf`, if enabled.
+    pub fn from_conf(conf: &Conf) -> Option<Self> {
+        match conf.ping_interval {
+            0 => None,
+            interval => Some(Pinger::new(
+                Duration::from_secs(interval),
+                Duration::from_secs(conf.ping_timeout),
+            )),
+        }
+    }
+
+    /// Time at which `tick` should be called next.
+    pub fn deadline(&self) -> Instant {
+        match &self.pending {
+            Some((_, sent)) => *sent + self.timeout,
+            None => self.next_ping,
+        }
+    }
+
+    /// Return the argument of a ping to send at `now`, if due. Fails when
+    /// the pending ping was not answered in time.
+    pub fn tick(&mut self, now: Instant) -> Result<Option<String>> {
+        match &self.pending {
+            Some((_, sent)) if now >= *sent + self.timeout => Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "no answer to ping from the relay",
+            )
+            .into()),
+            None if now >= self.next_ping => {
+                self.count += 1;
+                let arg = format!("weesels{}", self.count);
+                self.pending = Some((arg.clone(), now));
+                Ok(Some(arg))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Handle the pong of `arg`, received at `now`.
+    pub fn pong(&mut self, arg: &str, now: Instant) {
+        match &self.pending {
+            Some((sent_arg, sent)) if sent_arg == arg => {
+                self.lag = Some(now.saturating_duration_since(*sent));
+                self.next_ping = now + self.interval;
+                self.pending = None;
+            }
+            _ => (),
+        }
+    }
+
+    /// Last measured lag, or the time waited for a pending pong when longer.
+    pub fn lag(&self, now: Instant) -> Option<Duration> {
+        let waiting = self
+            .pending
+            .as_ref()
+            .map(|(_, sent)| now.saturating_duration_since(*sent));
+        match (self.lag, waiting) {
+            (Some(lag), Some(waiting)) => Some(lag.max(waiting)),
+            (lag, waiting) => lag.or(waiting),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pinger() {
+        let second = Duration::from_secs(1);
+        let mut pinger = Pinger::new(10 * second, 5 * second);
+        let start = pinger.deadline() - 10 * second;
+        assert_eq!(None, pinger.tick(start + second).unwrap());
+        assert_eq!(None, pinger.lag(start + second));
+
+        let arg = pinger.tick(start + 10 * second).unwrap().unwrap();
+        assert_eq!(start + 15 * second, pinger.deadline());
+        assert_eq!(Some(2 * second), pinger.lag(start + 12 * second));
+        // unrelated pongs are ignored.
+        pinger.pong("other", start + 12 * second);
+        pinger.pong(&arg, start + 13 * second);
+        assert_eq!(Some(3 * second), pinger.lag(start + 14 * second));
+        assert_eq!(start + 23 * second, pinger.deadline());
+
+        let arg = pinger.tick(start + 23 * second).unwrap().unwrap();
+        assert_eq!(Some(3 * second), pinger.lag(start + 24 * second));
+        assert_eq!(None, pinger.tick(start + 27 * second).unwrap());
+        assert!(pinger
+            .tick(start + 28 * second)
+            .unwrap_err()
+            .is_disconnect());
+        pinger.pong(&arg, start + 29 * second);
+        assert_eq!(None, pinger.tick(start + 29 * second).unwrap());
+    }
+}
@@ -0,0 +1,261 @@
+//! Recording of the frames exchanged with the relay, to replay sessions
+//! without a network.
+//!
+//! A recording is a text file with one frame per line: the milliseconds
+//! since the recording started, the direction ("<" received, ">" sent) and
+//! the raw frame in hex. Lines starting with "#" are comments.
+use super::{Error, Result, Stream};
+use log::error;
+use smol::io::{AsyncBufReadExt, AsyncWriteExt};
+use smol::net::unix::UnixStream;
+use smol::stream::StreamExt;
+use std::collections::HashSet;
+use std::io::{BufRead, LineWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Direction {
+    Received,
+    Sent,
+}
+
+impl Direction {
+    fn marker(self) -> &'static str {
+        match self {
+            Direction::Received => "<",
+            Direction::Sent => ">",
+        }
+    }
+}
+
+/// Recorded frame.
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    /// Time since the recording started.
+    pub at: Duration,
+    pub direction: Direction,
+    pub data: Vec<u8>,
+}
+
+/// Shared handle writing frames to a recording, once started.
+///
+/// The authentication exchange is never recorded.
+#[derive(Clone, Default)]
+pub struct Recorder {
+    recording: Arc<Mutex<Option<Recording>>>,
+}
+
+struct Recording {
+    start: Instant,
+    out: LineWriter<std::fs::File>,
+}
+
+impl Recorder {
+    /// Start recording to `path`, replacing any existing file.
+    pub fn start(&self, path: &Path) -> Result<()> {
+        let mut out = LineWriter::new(std::fs::File::create(path)?);
+        let now = time::OffsetDateTime::now_utc();
+        writeln!(
+            out,
+            "# weesels recording, started {}",
+            now.format("%F %T UTC")
+        )?;
+        *self.recording.lock().unwrap() = Some(Recording {
+            start: Instant::now(),
+            out,
+        });
+        Ok(())
+    }
+
+    pub fn record(&self, direction: Direction, data: &[u8]) {
+        let mut recording = self.recording.lock().unwrap();
+        if let Some(Recording { start, out }) = recording.as_mut() {
+            let res = writeln!(
+                out,
+                "{} {} {}",
+                start.elapsed().as_millis(),
+                direction.marker(),
+                hex::encode(data)
+            );
+            if let Err(e) = res {
+                error!("Stopped recording: {}", e);
+                *recording = None;
+            }
+        }
+    }
+}
+
+/// Read the entries of a recording.
+pub fn load(path: &Path) -> Result<Vec<Entry>> {
+    let file = std::io::BufReader::new(std::fs::File::open(path)?);
+    let mut entries = vec![];
+    for line in file.lines() {
+        let line = line?;
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        entries.push(parse_entry(&line).ok_or(Error::ProtocolError("invalid recording line"))?);
+    }
+    Ok(entries)
+}
+
+fn parse_entry(line: &str) -> Option<Entry> {
+    let mut fields = line.split(' ');
+    let at = Duration::from_millis(fields.next()?.parse().ok()?);
+    let direction = match fields.next()? {
+        "<" => Direction::Received,
+        ">" => Direction::Sent,
+        _ => return None,
+    };
+    let data = hex::decode(fields.next()?).ok()?;
+    if fields.next().is_some() {
+        return None;
+    }
+    Some(Entry {
+        at,
+        direction,
+        data,
+    })
+}
+
+/// Id of a command sent with `Wee::request()`, whose response is dropped
+/// unless it was requested first.
+fn request_id(command: &[u8]) -> Option<&[u8]> {
+    let command = command.strip_prefix(b"(")?;
+    let end = command.iter().position(|c| *c == b')')?;
+    Some(&command[..end]).filter(|id| id.starts_with(b"req_"))
+}
+
+/// Return a stream yielding the received frames of `entries` at their
+/// recorded time. Frames are held until the client has sent the requests
+/// recorded before them, other sent commands are discarded. The stream
+/// stays open after the last frame.
+pub fn replay(entries: Vec<Entry>) -> Result<Box<dyn Stream>> {
+    let (client, server) = UnixStream::pair()?;
+    let (requested, requests) = async_channel::unbounded();
+    let commands = smol::io::BufReader::new(server.clone());
+    smol::spawn(async move {
+        let mut lines = commands.split(b'\n');
+        while let Some(Ok(command)) = lines.next().await {
+            if let Some(id) = request_id(&command) {
+                if requested.send(id.to_vec()).await.is_err() {
+                    return;
+                }
+            }
+        }
+    })
+    .detach();
+    smol::spawn(async move {
+        let start = Instant::now();
+        let mut server = server;
+        let mut sent = HashSet::new();
+        for entry in entries {
+            if entry.direction == Direction::Sent {
+                if let Some(id) = request_id(&entry.data) {
+                    while !sent.contains(id) {
+                        match requests.recv().await {
+                            Ok(id) => sent.insert(id),
+                            // the client is gone.
+                            Err(_) => return,
+                        };
+                    }
+                }
+                continue;
+            }
+            smol::Timer::at(start + entry.at).await;
+            if let Err(e) = server.write_all(&entry.data).await {
+                error!("Replay interrupted: {}", e);
+                return;
+            }
+        }
+    })
+    .detach();
+    Ok(Box::new(client))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_load() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("session.rec");
+        let recorder = Recorder::default();
+        // nothing is written until started.
+        recorder.record(Direction::Sent, b"(id) info version\n");
+        recorder.start(&path).unwrap();
+        recorder.record(Direction::Sent, b"(id) info version\n");
+        recorder.clone().record(Direction::Received, b"\x00\x01");
+
+        let entries = load(&path).unwrap();
+        assert_eq!(2, entries.len());
+        assert_eq!(Direction::Sent, entries[0].direction);
+        assert_eq!(b"(id) info version\n", &entries[0].data[..]);
+        assert_eq!(Direction::Received, entries[1].direction);
+        assert_eq!(b"\x00\x01", &entries[1].data[..]);
+        assert!(entries[0].at <= entries[1].at);
+    }
+
+    #[test]
+    fn test_request_id() {
+        assert_eq!(
+            Some(&b"req_3"[..]),
+            request_id(b"(req_3) hdata buffer:0x1\n")
+        );
+        assert_eq!(None, request_id(b"(sync) sync *\n"));
+        assert_eq!(None, request_id(b"(req_3 hdata"));
+        assert_eq!(None, request_id(b"info version"));
+    }
+
+    #[test]
+    fn test_replay_waits_for_requests() {
+        smol::block_on(async {
+            let entries = vec![
+                Entry {
+                    at: Duration::from_millis(0),
+                    direction: Direction::Sent,
+                    data: b"(req_0) info version\n".to_vec(),
+                },
+                Entry {
+                    at: Duration::from_millis(0),
+                    direction: Direction::Received,
+                    data: b"\x00\x01".to_vec(),
+                },
+            ];
+            let mut stream = replay(entries).unwrap();
+            let mut received = [0u8; 2];
+            let early = smol::io::AsyncReadExt::read(&mut stream, &mut received);
+            let timeout = async {
+                smol::Timer::after(Duration::from_millis(50)).await;
+                Ok(0)
+            };
+            assert_eq!(0, smol::future::or(early, timeout).await.unwrap());
+
+            stream.write_all(b"(req_0) info version\n").await.unwrap();
+            smol::io::AsyncReadExt::read_exact(&mut stream, &mut received)
+                .await
+                .unwrap();
+            assert_eq!(b"\x00\x01", &received);
+        });
+    }
+
+    #[test]
+    fn test_parse_entry() {
+        assert_eq!(
+            Some(Entry {
+                at: Duration::from_millis(1500),
+                direction: Direction::Received,
+                data: vec![0xab, 0x01],
+            }),
+            parse_entry("1500 < ab01")
+        );
+        assert_eq!(None, parse_entry("1500 <ab01"));
+        assert_eq!(None, parse_entry("1500 = ab01"));
+        assert_eq!(None, parse_entry("x < ab01"));
+        assert_eq!(None, parse_entry("1500 < xyz"));
+        assert_eq!(None, parse_entry("1500 < ab01 ab"));
+    }
+}
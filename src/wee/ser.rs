@@ -0,0 +1,741 @@
+//! Serialization to the weechat relay binary format.
+//!
+//! Values are first turned into an `Object`, then encoded. Wee types are
+//! inferred from rust types (i8 as chr, i32 as int, i64 as lon, strings as
+//! str, sequences as arr, maps and structs as htb), except where the message
+//! field names the type (e.g. `hda`, `inf`, `inl`), or the value is wrapped in
+//! `Typed`.
+//!
+//! Like in decoding, message structs start with an `id` field, and hdata
+//! fields named `ptr_<name>` make the h-path.
+use super::de::Error;
+use serde::ser;
+use serde::Serialize;
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Types which can be given by field names or `Typed`.
+const WEE_TYPES: &[&str] = &[
+    "chr", "int", "lon", "str", "buf", "ptr", "tim", "htb", "hda", "inf", "inl", "arr",
+];
+
+impl ser::Error for Error {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        Error::Message(msg.to_string())
+    }
+}
+
+/// Serialize a value as wee type `.0`, e.g. a string as a "ptr".
+pub struct Typed<T>(pub &'static str, pub T);
+
+impl<T: Serialize> Serialize for Typed<T> {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_newtype_struct(self.0, &self.1)
+    }
+}
+
+/// Serialize a message, including the id, but excluding the frame header.
+pub fn to_bytes<T: Serialize>(msg: &T) -> Result<Vec<u8>> {
+    let mut out = vec![];
+    let (id, objects): (Object, Vec<(&str, Object)>) = match msg.serialize(ObjectSerializer)? {
+        Object::Struct(mut fields) if !fields.is_empty() && fields[0].0 == "id" => {
+            let id = fields.remove(0).1;
+            let objects = fields
+                .into_iter()
+                .map(|(name, obj)| match WEE_TYPES.contains(&name) {
+                    true => (name, obj),
+                    false => (obj.typ(), obj),
+                })
+                .collect();
+            (id, objects)
+        }
+        Object::Seq(mut elements) if !elements.is_empty() => {
+            let id = elements.remove(0);
+            let objects = elements.into_iter().map(|obj| (obj.typ(), obj)).collect();
+            (id, objects)
+        }
+        _ => {
+            return Err(Error::Message(String::from(
+                "expected a message with an id",
+            )))
+        }
+    };
+    encode(&id, "str", &mut out)?;
+    for (typ, obj) in objects.iter() {
+        out.extend_from_slice(typ.as_bytes());
+        encode(obj, typ, &mut out)?;
+    }
+    Ok(out)
+}
+
+/// Serialize a message as an uncompressed frame.
+pub fn to_frame<T: Serialize>(msg: &T) -> Result<Vec<u8>> {
+    let body = to_bytes(msg)?;
+    let mut frame = ((body.len() + 5) as u32).to_be_bytes().to_vec();
+    frame.push(0);
+    frame.extend(body);
+    Ok(frame)
+}
+
+/// Intermediate representation of serialized values.
+#[derive(Clone, Debug)]
+enum Object {
+    Chr(i8),
+    Int(i32),
+    Lon(i64),
+    Str(String),
+    Buf(Vec<u8>),
+    Null,
+    Seq(Vec<Object>),
+    Map(Vec<(Object, Object)>),
+    Struct(Vec<(&'static str, Object)>),
+    Typed(&'static str, Box<Object>),
+}
+
+impl Object {
+    /// The wee type inferred for the object.
+    fn typ(&self) -> &'static str {
+        match self {
+            Object::Chr(_) => "chr",
+            Object::Int(_) => "int",
+            Object::Lon(_) => "lon",
+            Object::Str(_) | Object::Null => "str",
+            Object::Buf(_) => "buf",
+            Object::Seq(_) => "arr",
+            Object::Map(_) | Object::Struct(_) => "htb",
+            Object::Typed(typ, _) => typ,
+        }
+    }
+
+    fn as_i64(&self) -> Option<i64> {
+        match self {
+            Object::Chr(v) => Some(*v as i64),
+            Object::Int(v) => Some(*v as i64),
+            Object::Lon(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    /// Key and values of a map or struct.
+    fn entries(&self) -> Option<Vec<(Object, &Object)>> {
+        match self {
+            Object::Map(entries) => Some(entries.iter().map(|(k, v)| (k.clone(), v)).collect()),
+            Object::Struct(fields) => Some(
+                fields
+                    .iter()
+                    .map(|(k, v)| (Object::Str(String::from(*k)), v))
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+}
+
+fn mismatch(obj: &Object, typ: &str) -> Error {
+    Error::Message(format!("can't encode {:?} as {}", obj, typ))
+}
+
+/// Write a length prefixed (by one byte) string, as used by lon, ptr and tim.
+fn encode_short(s: &str, out: &mut Vec<u8>) -> Result<()> {
+    if s.len() > 255 {
+        return Err(Error::Message(format!("{:?} is too long", s)));
+    }
+    out.push(s.len() as u8);
+    out.extend_from_slice(s.as_bytes());
+    Ok(())
+}
+
+fn encode_len(len: usize, out: &mut Vec<u8>) {
+    out.extend_from_slice(&(len as u32).to_be_bytes());
+}
+
+/// Write the data of `obj` as wee type `typ`, without the type marker.
+fn encode(obj: &Object, typ: &str, out: &mut Vec<u8>) -> Result<()> {
+    if let Object::Typed(_, inner) = obj {
+        return encode(inner, typ, out);
+    }
+    match (typ, obj) {
+        ("chr", Object::Chr(v)) => out.push(*v as u8),
+        ("int", obj) => match obj.as_i64() {
+            Some(v) if v >= i32::MIN as i64 && v <= i32::MAX as i64 => {
+                out.extend_from_slice(&(v as i32).to_be_bytes())
+            }
+            _ => return Err(mismatch(obj, typ)),
+        },
+        ("lon", obj) | ("tim", obj) => match (obj, obj.as_i64()) {
+            (_, Some(v)) => encode_short(&v.to_string(), out)?,
+            (Object::Str(s), None) => encode_short(s, out)?,
+            _ => return Err(mismatch(obj, typ)),
+        },
+        ("ptr", obj) => match (obj, obj.as_i64()) {
+            (Object::Null, _) => encode_short("0", out)?,
+            (_, Some(v)) => encode_short(&format!("{:x}", v), out)?,
+            (Object::Str(s), None) => {
+                let s = s.trim_start_matches("0x");
+                encode_short(if s.is_empty() { "0" } else { s }, out)?
+            }
+            _ => return Err(mismatch(obj, typ)),
+        },
+        ("str", Object::Null) | ("buf", Object::Null) => out.extend_from_slice(b"\xff\xff\xff\xff"),
+        ("str", Object::Str(s)) => {
+            encode_len(s.len(), out);
+            out.extend_from_slice(s.as_bytes());
+        }
+        ("buf", Object::Buf(b)) => {
+            encode_len(b.len(), out);
+            out.extend_from_slice(b);
+        }
+        ("arr", Object::Seq(elements)) => {
+            let etyp = elements.first().map_or("str", Object::typ);
+            out.extend_from_slice(etyp.as_bytes());
+            encode_len(elements.len(), out);
+            for e in elements {
+                encode(e, etyp, out)?;
+            }
+        }
+        ("htb", obj) if obj.entries().is_some() => {
+            let entries = obj.entries().unwrap_or_default();
+            let (ktyp, vtyp) = entries
+                .first()
+                .map_or(("str", "str"), |(k, v)| (k.typ(), v.typ()));
+            out.extend_from_slice(ktyp.as_bytes());
+            out.extend_from_slice(vtyp.as_bytes());
+            encode_len(entries.len(), out);
+            for (k, v) in entries {
+                encode(&k, ktyp, out)?;
+                encode(v, vtyp, out)?;
+            }
+        }
+        ("inf", Object::Seq(pair)) if pair.len() == 2 => {
+            encode(&pair[0], "str", out)?;
+            encode(&pair[1], "str", out)?;
+        }
+        ("hda", Object::Seq(items)) => encode_hda(items, out)?,
+        ("inl", Object::Seq(pair)) if pair.len() == 2 => {
+            encode(&pair[0], "str", out)?;
+            let items = match &pair[1] {
+                Object::Seq(items) => items,
+                obj => return Err(mismatch(obj, typ)),
+            };
+            encode_len(items.len(), out);
+            for item in items {
+                let vars = item.entries().ok_or_else(|| mismatch(item, typ))?;
+                encode_len(vars.len(), out);
+                for (name, value) in vars {
+                    encode(&name, "str", out)?;
+                    out.extend_from_slice(value.typ().as_bytes());
+                    encode(value, value.typ(), out)?;
+                }
+            }
+        }
+        (typ, obj) => return Err(mismatch(obj, typ)),
+    }
+    Ok(())
+}
+
+/// Write hdata items, which must all have the same fields as the first.
+fn encode_hda(items: &[Object], out: &mut Vec<u8>) -> Result<()> {
+    let items = items
+        .iter()
+        .map(|i| i.entries().ok_or_else(|| mismatch(i, "hda")))
+        .collect::<Result<Vec<_>>>()?;
+    let mut hpath = vec![];
+    let mut keys = vec![];
+    for (key, value) in items.first().into_iter().flatten() {
+        match key {
+            Object::Str(key) if key.starts_with("ptr_") => hpath.push(&key[4..]),
+            Object::Str(key) => keys.push(format!("{}:{}", key, value.typ())),
+            key => return Err(mismatch(key, "hda key")),
+        }
+    }
+    if items.is_empty() {
+        encode(&Object::Null, "str", out)?;
+        encode(&Object::Null, "str", out)?;
+    } else {
+        encode(&Object::Str(hpath.join("/")), "str", out)?;
+        encode(&Object::Str(keys.join(",")), "str", out)?;
+    }
+    encode_len(items.len(), out);
+    for item in items.iter() {
+        for (key, value) in item {
+            match key {
+                Object::Str(key) if key.starts_with("ptr_") => encode(value, "ptr", out)?,
+                _ => encode(value, value.typ(), out)?,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Serializer of any value to an `Object`.
+struct ObjectSerializer;
+
+/// Collects elements of sequences and fields of structs.
+struct Collect {
+    elements: Vec<Object>,
+    fields: Vec<(&'static str, Object)>,
+    entries: Vec<(Object, Object)>,
+    key: Option<Object>,
+}
+
+impl Collect {
+    fn new() -> Self {
+        Collect {
+            elements: vec![],
+            fields: vec![],
+            entries: vec![],
+            key: None,
+        }
+    }
+}
+
+impl ser::Serializer for ObjectSerializer {
+    type Ok = Object;
+    type Error = Error;
+    type SerializeSeq = Collect;
+    type SerializeTuple = Collect;
+    type SerializeTupleStruct = Collect;
+    type SerializeTupleVariant = ser::Impossible<Object, Error>;
+    type SerializeMap = Collect;
+    type SerializeStruct = Collect;
+    type SerializeStructVariant = ser::Impossible<Object, Error>;
+
+    fn serialize_bool(self, v: bool) -> Result<Object> {
+        Ok(Object::Chr(v as i8))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Object> {
+        Ok(Object::Chr(v))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Object> {
+        Ok(Object::Int(v as i32))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Object> {
+        Ok(Object::Int(v))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Object> {
+        Ok(Object::Lon(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Object> {
+        Ok(Object::Chr(v as i8))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Object> {
+        Ok(Object::Int(v as i32))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Object> {
+        Ok(Object::Lon(v as i64))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Object> {
+        Ok(Object::Lon(v as i64))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Object> {
+        Ok(Object::Str(v.to_string()))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Object> {
+        Ok(Object::Str(v.to_string()))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Object> {
+        Ok(Object::Str(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Object> {
+        Ok(Object::Str(String::from(v)))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Object> {
+        Ok(Object::Buf(v.to_vec()))
+    }
+
+    fn serialize_none(self) -> Result<Object> {
+        Ok(Object::Null)
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<Object> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Object> {
+        Ok(Object::Null)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Object> {
+        Ok(Object::Null)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<Object> {
+        Ok(Object::Str(String::from(variant)))
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<Object> {
+        let obj = value.serialize(self)?;
+        match WEE_TYPES.iter().find(|t| **t == name) {
+            Some(typ) => Ok(Object::Typed(typ, Box::new(obj))),
+            None => Ok(obj),
+        }
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Object> {
+        Err(Error::Message(format!("can't serialize enum {}", name)))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Collect> {
+        Ok(Collect::new())
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Collect> {
+        Ok(Collect::new())
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> Result<Collect> {
+        Ok(Collect::new())
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant> {
+        Err(Error::Message(format!("can't serialize enum {}", name)))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Collect> {
+        Ok(Collect::new())
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Collect> {
+        Ok(Collect::new())
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant> {
+        Err(Error::Message(format!("can't serialize enum {}", name)))
+    }
+}
+
+impl ser::SerializeSeq for Collect {
+    type Ok = Object;
+    type Error = Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        self.elements.push(value.serialize(ObjectSerializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Object> {
+        Ok(Object::Seq(self.elements))
+    }
+}
+
+impl ser::SerializeTuple for Collect {
+    type Ok = Object;
+    type Error = Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Object> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for Collect {
+    type Ok = Object;
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Object> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeMap for Collect {
+    type Ok = Object;
+    type Error = Error;
+
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<()> {
+        self.key = Some(key.serialize(ObjectSerializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        let key = self.key.take().ok_or(Error::Syntax)?;
+        self.entries.push((key, value.serialize(ObjectSerializer)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Object> {
+        Ok(Object::Map(self.entries))
+    }
+}
+
+impl ser::SerializeStruct for Collect {
+    type Ok = Object;
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.fields.push((key, value.serialize(ObjectSerializer)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Object> {
+        Ok(Object::Struct(self.fields))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::de::from_bytes;
+    use super::*;
+    use serde::Deserialize;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_serialize_version() {
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct Msg<'a> {
+            id: &'a str,
+            inf: (&'a str, &'a str),
+        }
+        let msg = Msg {
+            id: "version_check",
+            inf: ("version", "2.9"),
+        };
+        let encoded = to_bytes(&msg).unwrap();
+        assert_eq!(
+            &b"\x00\x00\x00\rversion_checkinf\x00\x00\x00\x07version\x00\x00\x00\x032.9"[..],
+            &encoded[..]
+        );
+        assert_eq!(msg, from_bytes(&encoded).unwrap());
+
+        let frame = to_frame(&msg).unwrap();
+        assert_eq!(&[0, 0, 0, 0x2b, 0], &frame[..5]);
+        assert_eq!(encoded, frame[5..].to_vec());
+    }
+
+    // Basic types round-trip
+    #[test]
+    fn test_serialize_test() {
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct Msg(
+            String,                // id
+            i8,                    // chr
+            i32,                   // int
+            i64,                   // lon
+            Option<String>,        // str
+            Option<String>,        // str
+            Vec<String>,           // arr
+            (i32, i32, i32),       // arr
+            BTreeMap<String, i32>, // htb
+        );
+        let msg = Msg(
+            String::from("test_msg"),
+            'A' as i8,
+            -123456,
+            -1234567890,
+            Some(String::from("a string")),
+            None,
+            vec![String::from("abc"), String::from("de")],
+            (123, 456, 789),
+            vec![(String::from("a"), 1), (String::from("b"), 2)]
+                .into_iter()
+                .collect(),
+        );
+        let encoded = to_bytes(&msg).unwrap();
+        assert_eq!(
+            &b"\x00\x00\x00\x08test_msgchrAint\xff\xfe\x1d\xc0lon\x0b-1234567890\
+            str\x00\x00\x00\x08a stringstr\xff\xff\xff\xffarrstr\x00\x00\x00\x02\
+            \x00\x00\x00\x03abc\x00\x00\x00\x02dearrint\x00\x00\x00\x03\x00\x00\
+            \x00{\x00\x00\x01\xc8\x00\x00\x03\x15htbstrint\x00\x00\x00\x02\x00\
+            \x00\x00\x01a\x00\x00\x00\x01\x00\x00\x00\x01b\x00\x00\x00\x02"[..],
+            &encoded[..]
+        );
+    }
+
+    #[test]
+    fn test_serialize_typed() {
+        #[derive(Serialize)]
+        struct Msg<'a> {
+            id: &'a str,
+            ptr: Typed<&'a str>,
+            tim: i64,
+            arr: Vec<Typed<Option<&'a str>>>,
+        }
+        let msg = Msg {
+            id: "typed",
+            ptr: Typed("ptr", "0x1234abcd"),
+            tim: 1321993456,
+            arr: vec![Typed("ptr", Some("12")), Typed("ptr", None)],
+        };
+        assert_eq!(
+            &b"\x00\x00\x00\x05typedptr\x081234abcdtim\n1321993456arrptr\x00\x00\x00\
+            \x02\x0212\x010"[..],
+            &to_bytes(&msg).unwrap()[..]
+        );
+    }
+
+    #[test]
+    fn test_serialize_hda() {
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct Hda<'a> {
+            ptr_bufs: &'a str,
+            number: i32,
+            full_name: &'a str,
+        }
+
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct Msg<'a> {
+            id: &'a str,
+            #[serde(borrow)]
+            hda: Vec<Hda<'a>>,
+        }
+        let msg = Msg {
+            id: "buffers",
+            hda: vec![
+                Hda {
+                    ptr_bufs: "0123",
+                    number: 1,
+                    full_name: "core.weechat",
+                },
+                Hda {
+                    ptr_bufs: "567",
+                    number: 2,
+                    full_name: "potato",
+                },
+            ],
+        };
+        let encoded = to_bytes(&msg).unwrap();
+        assert_eq!(
+            &b"\0\0\0\x07buffershda\0\0\0\x04bufs\0\0\0\x18number:int\
+            ,full_name:str\0\0\0\x02\x040123\0\0\0\x01\0\0\0\x0ccore.weechat\
+            \x03567\0\0\0\x02\0\0\0\x06potato"[..],
+            &encoded[..]
+        );
+        assert_eq!(msg, from_bytes(&encoded).unwrap());
+
+        let empty = Msg {
+            id: "buffers",
+            hda: vec![],
+        };
+        let encoded = to_bytes(&empty).unwrap();
+        assert_eq!(
+            &b"\0\0\0\x07buffershda\xFF\xFF\xFF\xFF\xFF\xFF\xFF\xFF\0\0\0\0"[..],
+            &encoded[..]
+        );
+        assert_eq!(empty, from_bytes(&encoded).unwrap());
+    }
+
+    #[test]
+    fn test_serialize_htb() {
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct Msg {
+            id: String,
+            htb: BTreeMap<String, String>,
+        }
+        let msg = Msg {
+            id: String::from("handshake"),
+            htb: vec![("nonce", "abcd"), ("totp", "off")]
+                .into_iter()
+                .map(|(k, v)| (String::from(k), String::from(v)))
+                .collect(),
+        };
+        assert_eq!(msg, from_bytes(&to_bytes(&msg).unwrap()).unwrap());
+    }
+
+    #[test]
+    fn test_serialize_inl() {
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct Server {
+            name: String,
+            is_connected: i32,
+            nick: Option<String>,
+        }
+
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct Msg {
+            id: String,
+            inl: (String, Vec<Server>),
+        }
+        let msg = Msg {
+            id: String::from("servers"),
+            inl: (
+                String::from("irc_server"),
+                vec![
+                    Server {
+                        name: String::from("libera"),
+                        is_connected: 1,
+                        nick: Some(String::from("bob")),
+                    },
+                    Server {
+                        name: String::from("oftc"),
+                        is_connected: 0,
+                        nick: None,
+                    },
+                ],
+            ),
+        };
+        assert_eq!(msg, from_bytes(&to_bytes(&msg).unwrap()).unwrap());
+    }
+
+    #[test]
+    fn test_serialize_errors() {
+        #[derive(Serialize)]
+        struct NoId {
+            inf: (String, String),
+        }
+        assert!(to_bytes(&NoId {
+            inf: (String::new(), String::new())
+        })
+        .is_err());
+
+        #[derive(Serialize)]
+        struct BadType {
+            id: String,
+            int: String,
+        }
+        assert!(to_bytes(&BadType {
+            id: String::new(),
+            int: String::from("nope"),
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,111 @@
+//! Per-buffer sync profiles, choosing which buffers the relay pushes lines
+//! for.
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// Events received for a buffer.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncProfile {
+    /// Lines and nicklist.
+    Full,
+    /// Only buffer list changes and hotlist, lines are fetched when shown.
+    Buffer,
+    /// Only buffer list changes, without hotlist.
+    None,
+}
+
+impl std::fmt::Display for SyncProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            SyncProfile::Full => "full",
+            SyncProfile::Buffer => "buffer",
+            SyncProfile::None => "none",
+        })
+    }
+}
+
+impl std::str::FromStr for SyncProfile {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "full" => Ok(SyncProfile::Full),
+            "buffer" => Ok(SyncProfile::Buffer),
+            "none" => Ok(SyncProfile::None),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Profiles by buffer full name. Names may end with "*" to match any
+/// buffer starting with the rest, the longest match wins.
+#[derive(Debug, Default)]
+pub struct SyncProfiles(HashMap<String, SyncProfile>);
+
+impl SyncProfiles {
+    pub fn new(profiles: HashMap<String, SyncProfile>) -> Self {
+        SyncProfiles(profiles)
+    }
+
+    /// Profile of a buffer, full unless configured otherwise.
+    pub fn get(&self, full_name: &str) -> SyncProfile {
+        if let Some(profile) = self.0.get(full_name) {
+            return *profile;
+        }
+        self.0
+            .iter()
+            .filter_map(|(pattern, profile)| {
+                let prefix = pattern.strip_suffix('*')?;
+                if full_name.starts_with(prefix) {
+                    Some((prefix.len(), *profile))
+                } else {
+                    None
+                }
+            })
+            .max_by_key(|(len, _)| *len)
+            .map_or(SyncProfile::Full, |(_, profile)| profile)
+    }
+
+    /// Whether all buffers are synced fully, as none is configured
+    /// otherwise.
+    pub fn all_full(&self) -> bool {
+        self.0.values().all(|profile| *profile == SyncProfile::Full)
+    }
+
+    pub fn set(&mut self, full_name: &str, profile: SyncProfile) {
+        self.0.insert(String::from(full_name), profile);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profiles() {
+        let mut profiles = SyncProfiles::new(
+            vec![
+                ("*", SyncProfile::Buffer),
+                ("irc.oftc.*", SyncProfile::None),
+                ("irc.oftc.#weechat", SyncProfile::Full),
+            ]
+            .into_iter()
+            .map(|(name, profile)| (String::from(name), profile))
+            .collect(),
+        );
+        assert_eq!(SyncProfile::Buffer, profiles.get("core.weechat"));
+        assert_eq!(SyncProfile::None, profiles.get("irc.oftc.#rust"));
+        assert_eq!(SyncProfile::Full, profiles.get("irc.oftc.#weechat"));
+        profiles.set("irc.oftc.#rust", SyncProfile::Full);
+        assert_eq!(SyncProfile::Full, profiles.get("irc.oftc.#rust"));
+        assert_eq!(
+            SyncProfile::Full,
+            SyncProfiles::default().get("core.weechat")
+        );
+        assert!(!profiles.all_full());
+        assert!(SyncProfiles::default().all_full());
+        assert_eq!(Ok(SyncProfile::None), "none".parse());
+        assert!("all".parse::<SyncProfile>().is_err());
+    }
+}
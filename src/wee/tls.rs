@@ -0,0 +1,255 @@
+//! TLS layer of relay connections, with the verification options of the
+//! configuration.
+use super::{Error, Result, Stream};
+use crate::config::Conf;
+use async_native_tls::{Certificate, Identity, TlsConnector};
+use log::trace;
+use openssl::pkcs12::Pkcs12;
+use openssl::pkey::PKey;
+use openssl::x509::X509;
+use std::path::Path;
+
+/// Start TLS over `stream`, verifying the relay is `host`.
+pub async fn connect(conf: &Conf, host: &str, stream: Box<dyn Stream>) -> Result<Box<dyn Stream>> {
+    trace!("doing tls handshake");
+    let pin = conf
+        .fingerprint
+        .as_deref()
+        .map(parse_fingerprint)
+        .transpose()?;
+    let stream = connector(conf, pin.is_some())?
+        .connect(host, stream)
+        .await
+        .map_err(|e| match e.to_string() {
+            msg if msg.contains("certificate verify failed") => Error::CertificateError(format!(
+                "could not verify the relay certificate ({}). Set ca_file or fingerprint \
+                 to trust a self-signed certificate.",
+                msg
+            )),
+            _ => Error::from(e),
+        })?;
+    if let Some(pin) = pin {
+        let der = match stream.peer_certificate()? {
+            Some(cert) => cert.to_der()?,
+            None => {
+                return Err(Error::CertificateError(String::from(
+                    "no relay certificate",
+                )))
+            }
+        };
+        let actual = openssl::sha::sha256(&der);
+        if actual[..] != pin[..] {
+            return Err(Error::CertificateError(format!(
+                "relay certificate fingerprint {} does not match the configured one",
+                hex::encode(actual)
+            )));
+        }
+    }
+    Ok(Box::new(stream))
+}
+
+/// Build a connector. A pinned certificate is checked after the handshake,
+/// instead of verifying it against CAs.
+fn connector(conf: &Conf, pinned: bool) -> Result<TlsConnector> {
+    let mut connector = TlsConnector::new()
+        .danger_accept_invalid_certs(conf.insecure || pinned)
+        .danger_accept_invalid_hostnames(conf.insecure || pinned);
+    if let Some(path) = &conf.ca_file {
+        for cert in X509::stack_from_pem(&read(path)?)? {
+            connector = connector.add_root_certificate(Certificate::from_der(&cert.to_der()?)?);
+        }
+    }
+    match (&conf.client_cert, &conf.client_key) {
+        (Some(cert), key) => {
+            let cert_pem = read(cert)?;
+            // the key may be in the certificate file.
+            let key_pem = match key {
+                Some(key) => read(key)?,
+                None => cert_pem.clone(),
+            };
+            let key = PKey::private_key_from_pem(&key_pem)?;
+            let cert = X509::from_pem(&cert_pem)?;
+            let pkcs12 = Pkcs12::builder()
+                .name("weesels")
+                .pkey(&key)
+                .cert(&cert)
+                .build2("")?;
+            connector = connector.identity(Identity::from_pkcs12(&pkcs12.to_der()?, "")?);
+        }
+        (None, Some(_)) => {
+            return Err(Error::ConfigError("client_key requires client_cert"));
+        }
+        (None, None) => (),
+    }
+    Ok(connector)
+}
+
+fn read(path: &Path) -> Result<Vec<u8>> {
+    std::fs::read(path)
+        .map_err(|e| Error::CertificateError(format!("could not read {}: {}", path.display(), e)))
+}
+
+/// Parse a SHA-256 fingerprint in hex, with optional ":" separators.
+fn parse_fingerprint(fingerprint: &str) -> Result<Vec<u8>> {
+    match hex::decode(fingerprint.replace(':', "")) {
+        Ok(pin) if pin.len() == 32 => Ok(pin),
+        _ => Err(Error::ConfigError(
+            "fingerprint must be a SHA-256 hash, in hex",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::asn1::Asn1Time;
+    use openssl::hash::MessageDigest;
+    use openssl::pkey::Private;
+    use openssl::rsa::Rsa;
+    use openssl::ssl::{SslAcceptor, SslMethod, SslVerifyMode};
+    use openssl::x509::extension::SubjectAlternativeName;
+    use openssl::x509::X509NameBuilder;
+    use smol::io::AsyncReadExt;
+    use smol::Async;
+    use std::io::Write;
+    use std::net::{TcpListener, TcpStream};
+
+    fn self_signed(name: &str) -> (X509, PKey<Private>) {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut subject = X509NameBuilder::new().unwrap();
+        subject.append_entry_by_text("CN", name).unwrap();
+        let subject = subject.build();
+        let mut cert = X509::builder().unwrap();
+        cert.set_version(2).unwrap();
+        cert.set_subject_name(&subject).unwrap();
+        cert.set_issuer_name(&subject).unwrap();
+        cert.set_pubkey(&key).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        let san = SubjectAlternativeName::new()
+            .dns(name)
+            .build(&cert.x509v3_context(None, None))
+            .unwrap();
+        cert.append_extension(san).unwrap();
+        cert.sign(&key, MessageDigest::sha256()).unwrap();
+        (cert.build(), key)
+    }
+
+    /// TLS server answering "ok" to a single connection. With `client_ca`,
+    /// it requires a client certificate signed by it.
+    fn serve(cert: &X509, key: &PKey<Private>, client_ca: Option<X509>) -> u16 {
+        let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
+        acceptor.set_certificate(cert).unwrap();
+        acceptor.set_private_key(key).unwrap();
+        if let Some(ca) = client_ca {
+            acceptor.cert_store_mut().add_cert(ca).unwrap();
+            acceptor.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+        }
+        let acceptor = acceptor.build();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            if let Ok(mut stream) = acceptor.accept(stream) {
+                let _ = stream.write_all(b"ok");
+                let _ = stream.shutdown();
+            }
+        });
+        port
+    }
+
+    fn conf(extra: &str) -> Conf {
+        toml::from_str(&format!("host = 'localhost'\npassword = ''\n{}", extra)).unwrap()
+    }
+
+    fn write_pem(dir: &Path, name: &str, pem: Vec<u8>) -> String {
+        let path = dir.join(name);
+        std::fs::write(&path, pem).unwrap();
+        path.display().to_string()
+    }
+
+    async fn connect_to(port: u16, conf: &Conf) -> Result<String> {
+        let stream = Async::new(TcpStream::connect(("127.0.0.1", port))?)?;
+        let mut stream = connect(conf, "localhost", Box::new(stream)).await?;
+        let mut received = String::new();
+        stream.read_to_string(&mut received).await?;
+        Ok(received)
+    }
+
+    #[test]
+    fn test_verification() {
+        smol::block_on(async {
+            let dir = tempfile::TempDir::new().unwrap();
+            let (cert, key) = self_signed("localhost");
+            let fingerprint = hex::encode(cert.digest(MessageDigest::sha256()).unwrap());
+
+            let port = serve(&cert, &key, None);
+            match connect_to(port, &conf("")).await {
+                Err(Error::CertificateError(msg)) => assert!(msg.contains("ca_file")),
+                res => panic!("unexpected result {:?}", res),
+            }
+
+            let ca_file = write_pem(dir.path(), "ca.pem", cert.to_pem().unwrap());
+            let port = serve(&cert, &key, None);
+            let ca_conf = conf(&format!("ca_file = '{}'", ca_file));
+            assert_eq!("ok", connect_to(port, &ca_conf).await.unwrap());
+
+            let port = serve(&cert, &key, None);
+            let pin_conf = conf(&format!("fingerprint = '{}'", fingerprint.to_uppercase()));
+            assert_eq!("ok", connect_to(port, &pin_conf).await.unwrap());
+
+            let port = serve(&cert, &key, None);
+            let (other, _) = self_signed("localhost");
+            let other_fingerprint = hex::encode(other.digest(MessageDigest::sha256()).unwrap());
+            let wrong_pin = conf(&format!("fingerprint = '{}'", other_fingerprint));
+            match connect_to(port, &wrong_pin).await {
+                Err(Error::CertificateError(msg)) => assert!(msg.contains(&fingerprint)),
+                res => panic!("unexpected result {:?}", res),
+            }
+
+            let port = serve(&cert, &key, None);
+            assert_eq!(
+                "ok",
+                connect_to(port, &conf("insecure = true")).await.unwrap()
+            );
+        });
+    }
+
+    #[test]
+    fn test_client_certificate() {
+        smol::block_on(async {
+            let dir = tempfile::TempDir::new().unwrap();
+            let (cert, key) = self_signed("localhost");
+            let (client_cert, client_key) = self_signed("client");
+            let insecure = conf("insecure = true");
+
+            let port = serve(&cert, &key, Some(client_cert.clone()));
+            assert!(connect_to(port, &insecure).await.is_err());
+
+            let cert_file = write_pem(dir.path(), "client.pem", client_cert.to_pem().unwrap());
+            let key_file = write_pem(
+                dir.path(),
+                "client.key",
+                client_key.private_key_to_pem_pkcs8().unwrap(),
+            );
+            let port = serve(&cert, &key, Some(client_cert));
+            let mtls = conf(&format!(
+                "insecure = true\nclient_cert = '{}'\nclient_key = '{}'",
+                cert_file, key_file
+            ));
+            assert_eq!("ok", connect_to(port, &mtls).await.unwrap());
+        });
+    }
+
+    #[test]
+    fn test_parse_fingerprint() {
+        let hash = "ab".repeat(32);
+        assert_eq!(vec![0xab; 32], parse_fingerprint(&hash).unwrap());
+        let separated = vec!["AB"; 32].join(":");
+        assert_eq!(vec![0xab; 32], parse_fingerprint(&separated).unwrap());
+        assert!(parse_fingerprint("abcd").is_err());
+        assert!(parse_fingerprint(&"zz".repeat(32)).is_err());
+    }
+}
@@ -0,0 +1,452 @@
+//! Client side of the WebSocket protocol (RFC 6455), to reach relays behind
+//! an HTTP reverse proxy.
+//!
+//! The payload of data frames is exposed as a byte stream, so relay frames
+//! are decoded the same way as over a raw connection.
+use super::link::MAX_FRAME_SIZE;
+use super::{Error, Result};
+use futures::{AsyncRead, AsyncWrite};
+use log::trace;
+use smol::io::{AsyncReadExt, AsyncWriteExt};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Appended to the handshake key to compute the accept header.
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC11B87";
+/// Maximum size of the handshake response headers.
+const MAX_RESPONSE_SIZE: usize = 16 * 1024;
+const READ_CHUNK_SIZE: usize = 4096;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xa;
+
+/// WebSocket connection over `S`, once the handshake is done.
+pub struct WebSocket<S> {
+    inner: S,
+    /// Received bytes, not yet decoded as frames.
+    received: Vec<u8>,
+    /// Payload of decoded data frames, not yet read.
+    payload: Vec<u8>,
+    /// Encoded frames, not yet written.
+    pending: Vec<u8>,
+    closed: bool,
+}
+
+/// Upgrade `stream` to a WebSocket connection, requesting `path` from
+/// `host` with extra `headers`.
+pub async fn connect<S>(
+    mut stream: S,
+    host: &str,
+    path: &str,
+    headers: &HashMap<String, String>,
+) -> Result<WebSocket<S>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut nonce = [0u8; 16];
+    openssl::rand::rand_bytes(&mut nonce)?;
+    let key = openssl::base64::encode_block(&nonce);
+    let mut request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+         Sec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n",
+        path, host, key
+    );
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    request.push_str("\r\n");
+    trace!("websocket handshake to {}{}", host, path);
+    stream.write_all(request.as_bytes()).await?;
+    stream.flush().await?;
+
+    let mut response = vec![];
+    let end = loop {
+        if let Some(pos) = find(&response, b"\r\n\r\n") {
+            break pos + 4;
+        }
+        if response.len() > MAX_RESPONSE_SIZE {
+            return Err(Error::WebSocketError(String::from(
+                "handshake response too long",
+            )));
+        }
+        let mut chunk = [0u8; READ_CHUNK_SIZE];
+        let len = stream.read(&mut chunk).await?;
+        if len == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        response.extend_from_slice(&chunk[..len]);
+    };
+    // the relay may send frames right after the response.
+    let received = response.split_off(end);
+    check_response(&String::from_utf8_lossy(&response), &key)?;
+    Ok(WebSocket {
+        inner: stream,
+        received,
+        payload: vec![],
+        pending: vec![],
+        closed: false,
+    })
+}
+
+/// Check the handshake response accepts the upgrade requested with `key`.
+fn check_response(response: &str, key: &str) -> Result<()> {
+    let mut lines = response.lines();
+    let status = lines.next().unwrap_or_default();
+    if status.split(' ').nth(1) != Some("101") {
+        return Err(Error::WebSocketError(format!(
+            "upgrade refused: {}",
+            status
+        )));
+    }
+    let accept = lines
+        .filter_map(|l| {
+            let mut parts = l.splitn(2, ':');
+            Some((parts.next()?, parts.next()?.trim()))
+        })
+        .find(|(name, _)| name.eq_ignore_ascii_case("sec-websocket-accept"))
+        .map(|(_, value)| value);
+    let expected = openssl::base64::encode_block(&openssl::sha::sha1(
+        format!("{}{}", key, ACCEPT_GUID).as_bytes(),
+    ));
+    if accept != Some(expected.as_str()) {
+        return Err(Error::WebSocketError(String::from(
+            "invalid Sec-WebSocket-Accept header",
+        )));
+    }
+    Ok(())
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Encode a masked client frame.
+fn encode_frame(opcode: u8, payload: &[u8]) -> io::Result<Vec<u8>> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        len if len < 126 => frame.push(0x80 | len as u8),
+        len if len <= 0xffff => {
+            frame.push(0x80 | 126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(0x80 | 127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    let mut mask = [0u8; 4];
+    openssl::rand::rand_bytes(&mut mask).map_err(io::Error::other)?;
+    frame.extend_from_slice(&mask);
+    frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+    Ok(frame)
+}
+
+/// Frame decoded from the start of a buffer.
+struct Frame {
+    opcode: u8,
+    payload: Vec<u8>,
+    /// Number of bytes of the buffer used by the frame.
+    size: usize,
+}
+
+/// Decode the payload length and the size of a frame header, if `buf`
+/// starts with a complete one.
+fn decode_header(buf: &[u8]) -> Option<(usize, usize)> {
+    let masked = buf.get(1)? & 0x80 != 0;
+    let (len, pos) = match buf[1] & 0x7f {
+        126 => (
+            u16::from_be_bytes(buf.get(2..4)?.try_into().ok()?) as usize,
+            4,
+        ),
+        127 => (
+            u64::from_be_bytes(buf.get(2..10)?.try_into().ok()?) as usize,
+            10,
+        ),
+        len => (len as usize, 2),
+    };
+    let pos = if masked { pos + 4 } else { pos };
+    if buf.len() < pos {
+        return None;
+    }
+    Some((len, pos))
+}
+
+/// Decode a frame, if `buf` starts with a complete one.
+fn decode_frame(buf: &[u8]) -> Option<Frame> {
+    let opcode = buf.first()? & 0x0f;
+    let (len, pos) = decode_header(buf)?;
+    let mask = if buf[1] & 0x80 != 0 {
+        buf[pos - 4..pos].to_vec()
+    } else {
+        vec![0; 4]
+    };
+    let payload = buf.get(pos..pos.checked_add(len)?)?;
+    Some(Frame {
+        opcode,
+        payload: payload
+            .iter()
+            .enumerate()
+            .map(|(i, b)| b ^ mask[i % 4])
+            .collect(),
+        size: pos + len,
+    })
+}
+
+impl<S: AsyncWrite + Unpin> WebSocket<S> {
+    /// Decode the received frames, answering pings.
+    fn decode_received(&mut self) -> io::Result<()> {
+        while let Some(frame) = decode_frame(&self.received) {
+            self.received.drain(..frame.size);
+            match frame.opcode {
+                OP_CONTINUATION | OP_TEXT | OP_BINARY => self.payload.extend(frame.payload),
+                OP_PING => {
+                    let pong = encode_frame(OP_PONG, &frame.payload)?;
+                    self.pending.extend(pong);
+                }
+                OP_CLOSE => self.closed = true,
+                _ => (),
+            }
+        }
+        // the frame would be buffered until complete.
+        match decode_header(&self.received) {
+            Some((len, _)) if len > MAX_FRAME_SIZE => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "websocket frame too long",
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Write the pending frames.
+    fn poll_pending(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        while !self.pending.is_empty() {
+            match Pin::new(&mut self.inner).poll_write(cx, &self.pending) {
+                Poll::Ready(Ok(0)) => return Poll::Ready(Err(io::ErrorKind::WriteZero.into())),
+                Poll::Ready(Ok(len)) => {
+                    self.pending.drain(..len);
+                }
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for WebSocket<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            this.decode_received()?;
+            if !this.pending.is_empty() {
+                // pongs are sent when possible, without blocking reads.
+                if let Poll::Ready(Err(e)) = this.poll_pending(cx) {
+                    return Poll::Ready(Err(e));
+                }
+            }
+            if !this.payload.is_empty() {
+                let len = usize::min(buf.len(), this.payload.len());
+                buf[..len].copy_from_slice(&this.payload[..len]);
+                this.payload.drain(..len);
+                return Poll::Ready(Ok(len));
+            }
+            if this.closed {
+                return Poll::Ready(Ok(0));
+            }
+            let mut chunk = [0u8; READ_CHUNK_SIZE];
+            match Pin::new(&mut this.inner).poll_read(cx, &mut chunk) {
+                Poll::Ready(Ok(0)) => return Poll::Ready(Ok(0)),
+                Poll::Ready(Ok(len)) => this.received.extend_from_slice(&chunk[..len]),
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for WebSocket<S> {
+    /// Send `buf` as a single text frame.
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        match this.poll_pending(cx) {
+            Poll::Ready(Ok(())) => (),
+            other => return other.map(|r| r.map(|()| 0)),
+        }
+        this.pending.extend(encode_frame(OP_TEXT, buf)?);
+        // errors are reported on the next write or flush.
+        let _ = this.poll_pending(cx);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match this.poll_pending(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.inner).poll_flush(cx),
+            other => other,
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match this.poll_pending(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.inner).poll_close(cx),
+            other => other,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smol::net::unix::UnixStream;
+
+    /// Encode an unmasked server frame.
+    fn server_frame(opcode: u8, fin: bool, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![if fin { 0x80 } else { 0 } | opcode, payload.len() as u8];
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    /// Read the handshake request, and answer it.
+    async fn accept(server: &mut UnixStream) -> String {
+        let mut request = vec![];
+        while find(&request, b"\r\n\r\n").is_none() {
+            let mut b = [0u8; 1];
+            server.read_exact(&mut b).await.unwrap();
+            request.push(b[0]);
+        }
+        let request = String::from_utf8(request).unwrap();
+        let key = request
+            .lines()
+            .find_map(|l| l.strip_prefix("Sec-WebSocket-Key: "))
+            .unwrap();
+        let accept = openssl::base64::encode_block(&openssl::sha::sha1(
+            format!("{}{}", key, ACCEPT_GUID).as_bytes(),
+        ));
+        let response = format!(
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\
+             Connection: Upgrade\r\nsec-websocket-accept: {}\r\n\r\n",
+            accept
+        );
+        server.write_all(response.as_bytes()).await.unwrap();
+        request
+    }
+
+    #[test]
+    fn test_websocket() {
+        smol::block_on(async {
+            let (client, mut server) = UnixStream::pair().unwrap();
+            let mut headers = HashMap::new();
+            headers.insert(String::from("X-Token"), String::from("abc"));
+            let server_task = smol::spawn(async move {
+                let request = accept(&mut server).await;
+                // data split across frames, and a ping in between.
+                let mut frames = server_frame(OP_BINARY, false, b"\x00\x00\x00\x0a");
+                frames.extend(server_frame(OP_PING, true, b"hi"));
+                frames.extend(server_frame(OP_CONTINUATION, true, b"\x00abcde"));
+                server.write_all(&frames).await.unwrap();
+
+                let mut received = vec![];
+                let mut chunk = [0u8; 64];
+                let frames = loop {
+                    let len = server.read(&mut chunk).await.unwrap();
+                    received.extend_from_slice(&chunk[..len]);
+                    let pong = decode_frame(&received);
+                    let command = pong
+                        .as_ref()
+                        .and_then(|p| decode_frame(&received[p.size..]));
+                    if let (Some(pong), Some(command)) = (pong, command) {
+                        break (pong, command);
+                    }
+                };
+                server
+                    .write_all(&server_frame(OP_CLOSE, true, b""))
+                    .await
+                    .unwrap();
+                (request, frames)
+            });
+
+            let mut ws = connect(client, "relay.example", "/weechat", &headers)
+                .await
+                .unwrap();
+            let mut data = vec![0u8; 10];
+            ws.read_exact(&mut data).await.unwrap();
+            assert_eq!(b"\x00\x00\x00\x0a\x00abcde", &data[..]);
+            ws.write_all(b"(id) info version\n").await.unwrap();
+            ws.flush().await.unwrap();
+            assert_eq!(0, ws.read(&mut data).await.unwrap());
+
+            let (request, (pong, command)) = server_task.await;
+            assert!(request.starts_with("GET /weechat HTTP/1.1\r\nHost: relay.example\r\n"));
+            assert!(request.contains("\r\nX-Token: abc\r\n"));
+            assert_eq!(OP_PONG, pong.opcode);
+            assert_eq!(b"hi", &pong.payload[..]);
+            assert_eq!(OP_TEXT, command.opcode);
+            assert_eq!(b"(id) info version\n", &command.payload[..]);
+        });
+    }
+
+    #[test]
+    fn test_refused() {
+        smol::block_on(async {
+            let (client, mut server) = UnixStream::pair().unwrap();
+            server
+                .write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n")
+                .await
+                .unwrap();
+            match connect(client, "relay.example", "/", &HashMap::new()).await {
+                Err(Error::WebSocketError(msg)) => assert!(msg.contains("404 Not Found")),
+                Err(e) => panic!("unexpected error {}", e),
+                Ok(_) => panic!("upgrade accepted"),
+            }
+        });
+    }
+
+    #[test]
+    fn test_decode_frame() {
+        assert!(decode_frame(b"\x82").is_none());
+        assert!(decode_frame(b"\x82\x05abc").is_none());
+        let mut long = vec![0x82, 126, 0x01, 0x00];
+        long.extend(vec![7u8; 256]);
+        let frame = decode_frame(&long).unwrap();
+        assert_eq!(256, frame.payload.len());
+        assert_eq!(long.len(), frame.size);
+
+        let encoded = encode_frame(OP_TEXT, b"quit\n").unwrap();
+        let frame = decode_frame(&encoded).unwrap();
+        assert_eq!(OP_TEXT, frame.opcode);
+        assert_eq!(b"quit\n", &frame.payload[..]);
+    }
+
+    #[test]
+    fn test_frame_too_long() {
+        smol::block_on(async {
+            let (client, mut server) = UnixStream::pair().unwrap();
+            let server_task = smol::spawn(async move {
+                accept(&mut server).await;
+                let mut header = vec![0x82, 127];
+                header.extend_from_slice(&(MAX_FRAME_SIZE as u64 + 1).to_be_bytes());
+                server.write_all(&header).await.unwrap();
+                server
+            });
+            let mut ws = connect(client, "relay.example", "/", &HashMap::new())
+                .await
+                .unwrap();
+            let mut data = vec![0u8; 10];
+            let err = ws.read(&mut data).await.unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
+            drop(server_task.await);
+        });
+    }
+}